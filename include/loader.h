@@ -0,0 +1,40 @@
+/* C API for the loader, built with the `capi` feature. See `src/capi.rs` for the details of each
+ * function. */
+#ifndef LOADER_H
+#define LOADER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define LOADER_OK 0
+/* A pointer argument was null, or a string wasn't valid UTF-8. */
+#define LOADER_ERROR_INVALID_ARGUMENT (-1)
+/* The call failed for a reason without a result code. Other non-zero values are nn result codes. */
+#define LOADER_ERROR_FAILED (-2)
+
+typedef struct LoaderHandle LoaderHandle;
+
+/* Mounts every .nro file in `path` and stores a new handle in `out_handle`. */
+int32_t loader_mount_directory(uint64_t program_id, const char *path, LoaderHandle **out_handle);
+
+/* The number of modules which were mounted successfully. */
+size_t loader_module_count(const LoaderHandle *handle);
+
+/* The address of `name` in the first module which exports it, or NULL. */
+const void *loader_lookup_symbol(const LoaderHandle *handle, const char *name);
+
+/* Unloads every module. The handle still has to be freed with loader_free. */
+int32_t loader_unload_all(LoaderHandle *handle);
+
+/* Frees a handle without unloading anything. */
+void loader_free(LoaderHandle *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
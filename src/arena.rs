@@ -0,0 +1,181 @@
+use std::alloc::Layout;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+use crate::{discover, LoaderError, MountOptions, NroFile};
+
+/// Where module images and BSS buffers are allocated. [`Heap`] is used unless another allocator is
+/// set with [`MountOptions::allocator`].
+///
+/// # Safety
+/// A non-null pointer returned by `allocate` must be valid for reads and writes of `layout`, be
+/// aligned to it, and not overlap any other live allocation until it's passed to `free`.
+pub unsafe trait Allocator {
+    /// Allocates a buffer for `layout`, which always has a non-zero size and is aligned to at least
+    /// 0x1000. Returns null if there is no room.
+    fn allocate(&self, layout: Layout) -> *mut u8;
+
+    /// Frees a buffer once the module using it has been unloaded.
+    ///
+    /// # Safety
+    /// `memory` must have come from `allocate` on this allocator with the same `layout`.
+    unsafe fn free(&self, memory: *mut u8, layout: Layout);
+}
+
+/// Allocates every buffer separately with the global allocator.
+pub struct Heap;
+
+unsafe impl Allocator for Heap {
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        unsafe { std::alloc::alloc(layout) }
+    }
+
+    unsafe fn free(&self, memory: *mut u8, layout: Layout) {
+        std::alloc::dealloc(memory, layout);
+    }
+}
+
+/// A single page-aligned block reserved up front, which modules are packed into back to back.
+///
+/// Buffers aren't reused once freed: the block is only released as a whole, when the arena is
+/// dropped. Dropping it doesn't unload anything: the modules still have to be unloaded one by
+/// one, e.g. with [`MountInfo::unload_all`](crate::MountInfo::unload_all). Every module allocated
+/// from it keeps it alive, so it's only dropped once they have all been dropped. If any of them was
+/// dropped without being unloaded, `nn::ro` still has its buffers mapped, so the block is leaked
+/// instead of freed. Reloading a module allocates it again, so leave room if modules will be
+/// reloaded.
+///
+/// The arena has to be sized before anything is mounted, which [`Arena::for_directory`] does with
+/// a dry run over the plugins. That reads every plugin twice, once for sizing and once for
+/// mounting.
+pub struct Arena {
+    memory: *mut u8,
+    layout: Layout,
+    used: Mutex<usize>,
+    // Buffers handed out and not freed yet, which `nn::ro` may still be using
+    live: AtomicUsize,
+}
+
+// The block is only handed out through `used`, which is behind a mutex
+unsafe impl Send for Arena {}
+unsafe impl Sync for Arena {}
+
+impl Arena {
+    /// Reserves `size` bytes, rounded up to a whole page.
+    pub fn new(size: usize) -> Result<Self, LoaderError> {
+        let layout = Layout::from_size_align(align_up!(size.max(1), 0x1000), 0x1000)
+            .map_err(|_| LoaderError::OutOfMemory { size })?;
+        let memory = unsafe { std::alloc::alloc(layout) };
+        if memory.is_null() {
+            return Err(LoaderError::OutOfMemory { size: layout.size() });
+        }
+        Ok(Self { memory, layout, used: Mutex::new(0), live: AtomicUsize::new(0) })
+    }
+
+    /// Reserves enough for the image and BSS of every plugin `mount_from_directory_with_options`
+    /// would find in `path`, with each BSS sized by `GetBufferSize` like it is when mounting.
+    /// Plugins which are later skipped (e.g. as duplicates) are still counted. Plugins which can't
+    /// be read or parsed aren't, so if they (or any others) change before they're mounted, the
+    /// arena can run short: modules which don't fit then fail with [`LoaderError::OutOfMemory`].
+    pub fn for_directory<P: AsRef<Path>, F: Fn(&Path) -> bool>(
+        path: P,
+        validator: F,
+        options: &MountOptions
+    ) -> Result<Self, LoaderError> {
+        let reader = options.reader();
+        let size = discover(options, path.as_ref(), validator)?
+            .plugins
+            .into_iter()
+            .filter_map(|(path, _)| NroFile::prepare(&reader, &path).ok())
+            .filter_map(|(nro, _)| nro.buffer_size().ok().map(|bss_size| (nro.data.len(), bss_size)))
+            .map(|(image_size, bss_size)| {
+                align_up!(image_size, 0x1000) + align_up!(align_up!(bss_size + options.bss_headroom, options.bss_align), 0x1000)
+            })
+            .sum();
+        Self::new(size)
+    }
+
+    /// The size of the block.
+    pub fn capacity(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// How much of the block has been handed out, including the padding between buffers.
+    pub fn used(&self) -> usize {
+        *self.used.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+unsafe impl Allocator for Arena {
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        let mut used = self.used.lock().unwrap_or_else(PoisonError::into_inner);
+        let start = align_up!(*used, layout.align());
+        match start.checked_add(layout.size()) {
+            Some(end) if end <= self.capacity() => {
+                *used = end;
+                self.live.fetch_add(1, Ordering::Relaxed);
+                unsafe { self.memory.add(start) }
+            },
+            _ => std::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn free(&self, _memory: *mut u8, _layout: Layout) {
+        self.live.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        if *self.live.get_mut() == 0 {
+            unsafe { std::alloc::dealloc(self.memory, self.layout) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::tests::{nro_image, temp_dir};
+    use crate::{mount_from_directory_with_options, nro, ro};
+
+    #[test]
+    fn sized_for_directory() {
+        let dir = temp_dir("arena");
+        let images = [nro_image(1), nro_image(2)];
+        for (name, image) in ["a.nro", "b.nro"].into_iter().zip(&images) {
+            std::fs::write(dir.join(name), image).unwrap();
+        }
+
+        let expected: usize = images.iter()
+            .map(|image| align_up!(image.len(), 0x1000) + align_up!(nro::parse(image).unwrap().bss_size, 0x1000))
+            .sum();
+        let arena = Arc::new(Arena::for_directory(&dir, |_| true, &MountOptions::default()).unwrap());
+        assert_eq!(arena.capacity(), expected);
+
+        let options = MountOptions::default().allocator(arena.clone());
+        let info = mount_from_directory_with_options(0, &dir, |_| true, &options).unwrap();
+        assert!(info.modules.iter().all(Result::is_ok));
+        assert_eq!(arena.used(), arena.capacity());
+        info.unload_all().unwrap();
+        assert_eq!(ro::counts(), (0, 0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn running_short() {
+        let dir = temp_dir("arena-short");
+        std::fs::write(dir.join("a.nro"), nro_image(1)).unwrap();
+        let arena = Arc::new(Arena::new(0x1000).unwrap());
+
+        let options = MountOptions::default().allocator(arena);
+        let info = mount_from_directory_with_options(0, &dir, |_| true, &options).unwrap();
+        assert!(matches!(&info.modules[..], [Err(LoaderError::OutOfMemory { .. })]));
+        assert_eq!(ro::counts().1, 0);
+        info.unload_all().unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
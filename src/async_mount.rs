@@ -0,0 +1,140 @@
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+use crate::{prepare_isolated, LoaderError, MountInfo, MountOptions, PreparedPlugin};
+
+/// A piece of blocking work (reading and hashing a single plugin) handed to the `spawn_blocking`
+/// hook of [`mount_from_directory_async`].
+pub type BlockingTask = Box<dyn FnOnce() + Send + 'static>;
+
+struct Slot<T> {
+    // A panicking task leaves its payload here, to be resumed on the polling thread
+    value: Option<std::thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+struct Pending<T>(Arc<Mutex<Slot<T>>>);
+
+impl<T> Future for Pending<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut slot = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        match slot.value.take() {
+            Some(Ok(value)) => Poll::Ready(value),
+            Some(Err(payload)) => std::panic::resume_unwind(payload),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn spawn<T, S, F>(spawn_blocking: &S, task: F) -> Pending<T>
+where
+    T: Send + 'static,
+    S: Fn(BlockingTask),
+    F: FnOnce() -> T + Send + 'static
+{
+    let slot = Arc::new(Mutex::new(Slot { value: None, waker: None }));
+    let sender = slot.clone();
+    spawn_blocking(Box::new(move || {
+        let value = std::panic::catch_unwind(AssertUnwindSafe(task));
+        let mut slot = sender.lock().unwrap_or_else(PoisonError::into_inner);
+        slot.value = Some(value);
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    }));
+    Pending(slot)
+}
+
+/// Async version of [`crate::mount_from_directory_with_options`].
+///
+/// Reading and hashing each plugin is handed to `spawn_blocking`, which should run the task on
+/// whatever blocking pool the host's executor provides (e.g. `|task| { tokio::task::spawn_blocking(task); }`).
+/// Registration and mounting still happen on the thread polling the returned future, so that
+/// thread is the only one which ever calls into `nn::ro`.
+///
+/// Every task passed to `spawn_blocking` must eventually be run, otherwise the future never resolves.
+/// A task which panics (see [`MountOptions::catch_panics`]) resumes the panic on the polling thread.
+pub async fn mount_from_directory_async<P, F, S>(
+    program_id: u64,
+    path: P,
+    validator: F,
+    options: &MountOptions,
+    spawn_blocking: S
+) -> Result<MountInfo, LoaderError>
+where
+    P: AsRef<Path>,
+    F: Fn(&Path) -> bool,
+    S: Fn(BlockingTask)
+{
+    let crate::Discovered { plugins, hidden, duplicates } = crate::discover(options, path.as_ref(), validator)?;
+    let pending: Vec<_> = plugins.into_iter()
+        .map(|(path, priority)| {
+            let (reader, catch_panics) = (options.reader(), options.catch_panics);
+            spawn(&spawn_blocking, move || {
+                let nro = prepare_isolated(&reader, &path, catch_panics);
+                PreparedPlugin { path, priority, nro }
+            })
+        })
+        .collect();
+
+    let mut plugins = Vec::with_capacity(pending.len());
+    for plugin in pending {
+        let plugin = plugin.await;
+        options.hooks.fire_hashed(&plugin);
+        plugins.push(plugin);
+    }
+
+    crate::mount_plugins(program_id, plugins, hidden, duplicates, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::Wake;
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn poll<T>(pending: &mut Pending<T>, waker: &Arc<CountingWaker>) -> Poll<T> {
+        let waker = Waker::from(waker.clone());
+        Pin::new(pending).poll(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn value_is_handed_over() {
+        let waker = Arc::new(CountingWaker::default());
+        let tasks = Mutex::new(Vec::new());
+        let mut pending = spawn(&|task| tasks.lock().unwrap().push(task), || 7);
+        assert!(poll(&mut pending, &waker).is_pending());
+
+        for task in tasks.into_inner().unwrap() {
+            task();
+        }
+        assert_eq!(waker.0.load(Ordering::SeqCst), 1);
+        assert!(matches!(poll(&mut pending, &waker), Poll::Ready(7)));
+    }
+
+    #[test]
+    fn panics_reach_the_poller() {
+        let waker = Arc::new(CountingWaker::default());
+        let mut pending = spawn(&|task: BlockingTask| task(), || -> u32 { panic!("task failed") });
+        let payload = std::panic::catch_unwind(AssertUnwindSafe(|| poll(&mut pending, &waker))).err().unwrap();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"task failed"));
+    }
+}
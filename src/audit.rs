@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+
+use crate::{discover, nro, LoaderError, MountOptions, Sha256Hash};
+
+/// What [`audit_directory`] found out about a single file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub path: PathBuf,
+    pub file_size: usize,
+    /// The size in the NRO header, or `None` if the file is too short to have one.
+    pub declared_size: Option<usize>,
+    /// Whether the NRO header has the `NRO0` magic.
+    pub magic_ok: bool,
+    /// The hash of the file as it is on disk. `None` if it couldn't be read.
+    pub hash: Option<Sha256Hash>,
+    /// The hash the file is registered under when mounted, which is taken after the BSS size in
+    /// its header is fixed up the way mounting does. This is the one
+    /// [`MountOptions::on_hash`] reports and the NRR holds, and only differs from `hash` for
+    /// modules which needed fixing. `None` if the file couldn't be read or isn't a valid NRO.
+    pub mount_hash: Option<Sha256Hash>,
+    /// Why the file couldn't be read, in which case every other field but `path` is empty.
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    /// Whether the file looks like an intact NRO: it could be read, the magic is there, and the
+    /// header declares exactly the size of the file.
+    pub fn is_consistent(&self) -> bool {
+        self.error.is_none() && self.magic_ok && self.declared_size == Some(self.file_size)
+    }
+}
+
+/// Checks every file in `path` which passes `validator` against its NRO header, without mounting
+/// anything. Files are read as they are on disk, and are listed like
+/// [`crate::mount_from_directory`] would (so hidden files are left out).
+///
+/// Fails if the directory can't be listed. Files which can't be read are reported with
+/// [`AuditEntry::error`] set.
+pub fn audit_directory<P: AsRef<Path>, F: Fn(&Path) -> bool>(path: P, validator: F) -> Result<Vec<AuditEntry>, LoaderError> {
+    let options = MountOptions::default();
+    let entries = discover(&options, path.as_ref(), validator)?
+        .plugins
+        .into_iter()
+        .map(|(path, _)| match options.source.read(&path, None) {
+            Ok(data) => audit(path, data),
+            Err(e) => AuditEntry {
+                path,
+                file_size: 0,
+                declared_size: None,
+                magic_ok: false,
+                hash: None,
+                mount_hash: None,
+                error: Some(e.to_string()),
+            },
+        })
+        .collect();
+    Ok(entries)
+}
+
+fn audit(path: PathBuf, data: Vec<u8>) -> AuditEntry {
+    let mut fixed = data.clone();
+    let mount_hash = nro::parse(&data)
+        .and_then(|_| nro::fix_bss_size(&mut fixed))
+        .ok()
+        .map(|_| Sha256Hash::new(&fixed));
+    AuditEntry {
+        path,
+        file_size: data.len(),
+        declared_size: nro::declared_size(&data),
+        magic_ok: nro::has_magic(&data),
+        hash: Some(Sha256Hash::new(&data)),
+        mount_hash,
+        error: None,
+    }
+}
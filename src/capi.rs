@@ -0,0 +1,127 @@
+//! A C API for hosts which can't call the Rust API directly, enabled by the `capi` feature. The
+//! declarations are in `include/loader.h`.
+//!
+//! A mount is represented by an opaque [`LoaderHandle`], which has to be released with
+//! [`loader_free`]. Functions which can fail return [`LOADER_OK`] on success, the `nn` result
+//! code if the failure came from the SDK, or one of the negative `LOADER_ERROR_*` codes otherwise.
+
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
+use std::path::Path;
+
+use crate::{mount_from_directory, rc, LoaderError, MountInfo};
+
+pub const LOADER_OK: i32 = 0;
+/// A pointer argument was null, or a string wasn't valid UTF-8.
+pub const LOADER_ERROR_INVALID_ARGUMENT: i32 = -1;
+/// The call failed for a reason without a result code, e.g. a plugin directory which can't be read.
+pub const LOADER_ERROR_FAILED: i32 = -2;
+
+/// The plugins mounted by [`loader_mount_directory`].
+pub struct LoaderHandle {
+    // Taken by `loader_unload_all`
+    info: Option<MountInfo>,
+}
+
+fn error_code(error: &LoaderError) -> i32 {
+    match error {
+        LoaderError::RegistrationError(rc)
+        | LoaderError::MountError { rc, .. }
+        | LoaderError::InvalidModuleBuffer(rc)
+        | LoaderError::UnloadError(rc)
+        | LoaderError::UnregistrationError(rc)
+        | LoaderError::ProgramIdMismatch { rc, .. }
+        | LoaderError::LookupError { rc, .. } => *rc as i32,
+        LoaderError::OutOfAddressSpace { .. } => rc::OUT_OF_ADDRESS_SPACE as i32,
+        _ => LOADER_ERROR_FAILED,
+    }
+}
+
+/// Mounts every `.nro` file in the directory at `path`, a null-terminated UTF-8 string, and stores
+/// a new handle in `out_handle`. Plugins which fail to mount don't fail the call, they're just not
+/// counted by [`loader_module_count`].
+///
+/// # Safety
+/// `path` must be a valid null-terminated string and `out_handle` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn loader_mount_directory(
+    program_id: u64,
+    path: *const c_char,
+    out_handle: *mut *mut LoaderHandle
+) -> i32 {
+    if path.is_null() || out_handle.is_null() {
+        return LOADER_ERROR_INVALID_ARGUMENT;
+    }
+    let Ok(path) = CStr::from_ptr(path).to_str() else { return LOADER_ERROR_INVALID_ARGUMENT };
+
+    let is_nro = |path: &Path| matches!(path.extension(), Some(extension) if extension == "nro");
+    match mount_from_directory(program_id, path, is_nro) {
+        Ok(info) => {
+            *out_handle = Box::into_raw(Box::new(LoaderHandle { info: Some(info) }));
+            LOADER_OK
+        },
+        Err(e) => error_code(&e),
+    }
+}
+
+/// The number of modules which were mounted successfully, or 0 for a null handle or one which has
+/// been passed to [`loader_unload_all`].
+///
+/// # Safety
+/// `handle` must be null or a live handle from [`loader_mount_directory`].
+#[no_mangle]
+pub unsafe extern "C" fn loader_module_count(handle: *const LoaderHandle) -> usize {
+    match handle.as_ref().and_then(|handle| handle.info.as_ref()) {
+        Some(info) => info.iter_load_order().count(),
+        None => 0,
+    }
+}
+
+/// Looks up `name`, a null-terminated UTF-8 string, in each mounted module in load order and
+/// returns the first address found, or null if no module exports it.
+///
+/// # Safety
+/// `handle` must be null or a live handle from [`loader_mount_directory`], and `name` must be null
+/// or a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn loader_lookup_symbol(handle: *const LoaderHandle, name: *const c_char) -> *const c_void {
+    let Some(info) = handle.as_ref().and_then(|handle| handle.info.as_ref()) else { return std::ptr::null() };
+    if name.is_null() {
+        return std::ptr::null();
+    }
+    let Ok(name) = CStr::from_ptr(name).to_str() else { return std::ptr::null() };
+
+    info.iter_load_order()
+        .find_map(|module| module.symbol(name))
+        .map_or(std::ptr::null(), |address| address as *const c_void)
+}
+
+/// Unloads every module and unregisters the NRRs, see [`MountInfo::unload_all`]. The handle stays
+/// valid but empty, and still has to be freed with [`loader_free`]. Calling this again does nothing.
+///
+/// If a module fails to unload, it and the modules before it stay loaded, and can't be unloaded
+/// through the handle anymore.
+///
+/// # Safety
+/// `handle` must be null or a live handle from [`loader_mount_directory`].
+#[no_mangle]
+pub unsafe extern "C" fn loader_unload_all(handle: *mut LoaderHandle) -> i32 {
+    let Some(handle) = handle.as_mut() else { return LOADER_ERROR_INVALID_ARGUMENT };
+    match handle.info.take().map(MountInfo::unload_all) {
+        Some(Err(e)) => error_code(&e),
+        _ => LOADER_OK,
+    }
+}
+
+/// Frees a handle. Modules which are still mounted stay loaded, so call [`loader_unload_all`]
+/// first to unload them. A null handle is ignored.
+///
+/// # Safety
+/// `handle` must be null or a live handle from [`loader_mount_directory`], which mustn't be used
+/// again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn loader_free(handle: *mut LoaderHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::{MountInfo, Sha256Hash};
+
+/// What changed between two plugin scans, see [`scan_diff`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanDiff {
+    /// Plugins only in the new scan.
+    pub added: Vec<PathBuf>,
+    /// Plugins only in the old scan.
+    pub removed: Vec<PathBuf>,
+    /// Plugins in both scans whose hash changed.
+    pub modified: Vec<PathBuf>,
+}
+
+impl ScanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Compares two scans of `(path, hash)` pairs, matching plugins up by path. Each bucket keeps the
+/// order of the scan it came from.
+pub fn scan_diff(old: &[(PathBuf, Sha256Hash)], new: &[(PathBuf, Sha256Hash)]) -> ScanDiff {
+    let old_hashes: HashMap<&Path, Sha256Hash> = old.iter().map(|(path, hash)| (path.as_path(), *hash)).collect();
+    let new_hashes: HashMap<&Path, Sha256Hash> = new.iter().map(|(path, hash)| (path.as_path(), *hash)).collect();
+
+    let mut diff = ScanDiff::default();
+    for (path, hash) in new {
+        match old_hashes.get(path.as_path()) {
+            None => diff.added.push(path.clone()),
+            Some(old_hash) if old_hash != hash => diff.modified.push(path.clone()),
+            Some(_) => {},
+        }
+    }
+    diff.removed.extend(
+        old.iter()
+            .filter(|(path, _)| !new_hashes.contains_key(path.as_path()))
+            .map(|(path, _)| path.clone())
+    );
+    diff
+}
+
+impl MountInfo {
+    /// The `(path, hash)` of every mounted module which came from a file, for comparing against a
+    /// later scan with [`scan_diff`].
+    pub fn scan(&self) -> Vec<(PathBuf, Sha256Hash)> {
+        self.iter_load_order()
+            .filter_map(|module| Some((module.path()?.to_path_buf(), module.hash())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(entries: &[(&str, u8)]) -> Vec<(PathBuf, Sha256Hash)> {
+        entries.iter().map(|(path, byte)| (PathBuf::from(path), Sha256Hash([*byte; 0x20]))).collect()
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn identical_scans() {
+        let old = scan(&[("a.nro", 1), ("b.nro", 2)]);
+        assert!(scan_diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn buckets() {
+        let old = scan(&[("a.nro", 1), ("b.nro", 2), ("c.nro", 3)]);
+        let new = scan(&[("d.nro", 4), ("b.nro", 2), ("c.nro", 5), ("e.nro", 6)]);
+        assert_eq!(scan_diff(&old, &new), ScanDiff {
+            added: paths(&["d.nro", "e.nro"]),
+            removed: paths(&["a.nro"]),
+            modified: paths(&["c.nro"]),
+        });
+    }
+
+    #[test]
+    fn renamed_file_is_added_and_removed() {
+        let old = scan(&[("old.nro", 1)]);
+        let new = scan(&[("new.nro", 1)]);
+        assert_eq!(scan_diff(&old, &new), ScanDiff {
+            added: paths(&["new.nro"]),
+            removed: paths(&["old.nro"]),
+            modified: Vec::new(),
+        });
+    }
+
+    #[test]
+    fn empty_scans() {
+        let new = scan(&[("a.nro", 1)]);
+        assert_eq!(scan_diff(&[], &new).added, paths(&["a.nro"]));
+        assert_eq!(scan_diff(&new, &[]).removed, paths(&["a.nro"]));
+    }
+}
@@ -1,5 +1,12 @@
 #![feature(let_else)]
-use std::path::Path;
+use std::alloc::Layout;
+use std::borrow::Cow;
+use std::ffi::CString;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 use nn::ro::{NrrHeader, Module};
 use thiserror::Error;
 use nnsdk as nn;
@@ -10,193 +17,3245 @@ macro_rules! align_up {
     };
 }
 
+mod arena;
+pub use arena::{Allocator, Arena, Heap};
+
+#[cfg(feature = "capi")]
+pub mod capi;
+
+mod audit;
+pub use audit::{audit_directory, AuditEntry};
+
+mod diff;
+pub use diff::{scan_diff, ScanDiff};
+
+mod manager;
+pub use manager::{LruPolicy, PluginManager};
+
+mod manifest;
+
+pub mod nro;
+use nro::InvalidNroReason;
+pub use nro::PluginMeta;
+
+pub mod nrr;
+use nrr::{InvalidNrrReason, NrrBuilder, NrrKind};
+pub use nrr::{build_nrr_ordered, verify_nrr};
+
+pub mod rc;
+use rc::ResultCode;
+
+mod ro;
+
+mod registry;
+pub use registry::Registry;
+
+mod self_test;
+pub use self_test::self_test;
+
+mod snapshot;
+pub use snapshot::{PluginSnapshot, SnapshotEntry};
+
+mod source;
+pub use source::{NnFs, PluginSource, StdFs};
+
+mod svc;
+pub use svc::{available_code_space, MemoryPermission};
+
+mod transaction;
+pub use transaction::MountTransaction;
+
+#[cfg(feature = "async")]
+mod async_mount;
+#[cfg(feature = "async")]
+pub use async_mount::{mount_from_directory_async, BlockingTask};
+
+// ro expects the hash table to start directly after the 0x350 byte NRR header, with each entry
+// being a 0x20 byte SHA-256 hash. If the bindings' layout drifts from this, registration fails with
+// an opaque result code, so the assumption is checked at compile time.
+const NRR_HASHES_OFFSET: usize = 0x350;
+
+const _: () = assert!(std::mem::size_of::<NrrHeader>() == NRR_HASHES_OFFSET);
+const _: () = assert!(std::mem::size_of::<Sha256Hash>() == 0x20);
+
+/// The most modules a process can have loaded at once.
+///
+/// The SDK doesn't expose this, so it's the size of the per-process module table in `ro`'s
+/// implementation, 0x40, which has been the same on every firmware so far.
+pub const fn max_loaded_modules() -> usize {
+    0x40
+}
+
+/// The most NRRs a process can have registered at once. Like [`max_loaded_modules`], this is the
+/// size of `ro`'s per-process table (0x40), as the SDK doesn't expose it.
+pub const fn max_registered_nrrs() -> usize {
+    0x40
+}
+
+/// How many hashes to put in a single NRR, used when splitting plugins across NRRs.
+///
+/// `ro` doesn't limit the number of hashes in an NRR as such, so this is a conservative default
+/// rather than a platform limit: [`max_loaded_modules`], since an NRR never needs to authorize
+/// more modules than a process can load.
+pub const fn max_modules_per_nrr() -> usize {
+    max_loaded_modules()
+}
+
 #[derive(Error, Debug)]
 pub enum LoaderError {
     #[error("{0}")]
     IO(#[from] std::io::Error),
 
-    #[error("Error registering modules: {0:#x}")]
+    #[error("Error registering modules: {}", ResultCode(*.0))]
     RegistrationError(u32),
 
-    #[error("Error mounting module: {0:#x}")]
-    MountError(u32),
+    #[error("Error mounting module: {}", ResultCode(*rc))]
+    MountError {
+        rc: u32,
+        /// The image as it was handed to `LoadModule`, if [`MountOptions::retain_failed_buffers`]
+        /// was enabled.
+        image: Option<Vec<u8>>,
+        /// How many relocations the module has, from its dynamic section. `nn::ro` doesn't report
+        /// how many it applied before failing, so this is the total.
+        relocations_total: Option<usize>,
+    },
 
-    #[error("Error retrieving buffer size: {0:#x}")]
+    #[error("Error retrieving buffer size: {}", ResultCode(*.0))]
     InvalidModuleBuffer(u32),
+
+    #[error("Invalid NRO: {reason}")]
+    InvalidNro { reason: InvalidNroReason },
+
+    #[error("Invalid NRR: {reason}")]
+    InvalidNrr { reason: InvalidNrrReason },
+
+    #[error("NRR doesn't match its modules: {} missing, {} extra", missing.len(), extra.len())]
+    NrrHashMismatch {
+        /// Hashes of modules which aren't in the NRR.
+        missing: Vec<Sha256Hash>,
+        /// Hashes in the NRR which don't belong to any of the modules.
+        extra: Vec<Sha256Hash>,
+    },
+
+    #[error("Module name {name} is longer than {max} bytes")]
+    NameTooLong { name: String, max: usize },
+
+    #[error("{segment} segment at {address:#x} is {actual}, expected {expected}")]
+    PermissionMismatch {
+        segment: &'static str,
+        address: usize,
+        expected: MemoryPermission,
+        actual: MemoryPermission,
+    },
+
+    #[error("Memory attributes can't be queried on this target")]
+    MemoryQueryUnsupported,
+
+    #[error("Module hashes to {actual} at mount time, but {expected} was registered")]
+    HashMismatch { expected: Sha256Hash, actual: Sha256Hash },
+
+    #[error("No module named {name} is mounted")]
+    UnknownModule { name: String },
+
+    #[error("Out of memory allocating {size:#x} bytes")]
+    OutOfMemory { size: usize },
+
+    #[error("{name} is already loaded, it has to be unloaded before it can be mounted again")]
+    AlreadyLoaded { name: String },
+
+    #[error("Ran out of address space mounting {name}")]
+    OutOfAddressSpace { name: String },
+
+    #[error("Image is {size:#x} bytes, but only {capacity:#x} are available")]
+    ImageTooLarge { size: usize, capacity: usize },
+
+    #[error("BSS buffer is {provided:#x} bytes, but the module needs {required:#x}")]
+    BssTooSmall { required: usize, provided: usize },
+
+    #[error("Mapped module buffers must be aligned to and padded to 0x1000 bytes")]
+    MisalignedBuffer,
+
+    #[error("Self-test failed: {0}")]
+    SelfTestFailed(String),
+
+    #[error("Timed out reading {}", path.display())]
+    ReadTimeout { path: PathBuf },
+
+    #[error("Plugin directory {} doesn't exist", path.display())]
+    PluginDirMissing { path: PathBuf },
+
+    #[error("Module was not mounted from a file and can't be reloaded")]
+    NotReloadable,
+
+    #[error("NRR program id {nrr_program_id:#x} was rejected while running as {program_id:#x}: {}", ResultCode(*rc))]
+    ProgramIdMismatch { nrr_program_id: u64, program_id: u64, rc: u32 },
+
+    #[error("Error unloading module: {}", ResultCode(*.0))]
+    UnloadError(u32),
+
+    #[error("Symbol {name} was not found")]
+    SymbolNotFound { name: String },
+
+    #[error("{name:?} is not a valid symbol name")]
+    InvalidSymbolName { name: String },
+
+    #[error("Error looking up symbol {name}: {}", ResultCode(*rc))]
+    LookupError { name: String, rc: u32 },
+
+    #[error("Error unregistering modules: {}", ResultCode(*.0))]
+    UnregistrationError(u32),
+
+    #[error("{}: {source}", path.display())]
+    StrictModeViolation {
+        path: PathBuf,
+        source: Box<LoaderError>,
+    },
+
+    #[error("Panicked while reading {}", path.display())]
+    Panicked { path: PathBuf },
+
+    #[error("Plugin was skipped: {0}")]
+    Skipped(SkipReason),
+
+    #[error("Bootstrap plugin {name} was not found")]
+    UnmatchedBootstrap { name: String },
+
+    #[error("Plugin is identical to {}", original.display())]
+    DuplicatePlugin { original: PathBuf },
+
+    #[error(
+        "NRR kind {kind:?} requires firmware {}.{}.{} or later, but {}.{}.{} is running",
+        required.0, required.1, required.2, running.0, running.1, running.2
+    )]
+    UnsupportedNrrKind { kind: NrrKind, required: (u8, u8, u8), running: (u8, u8, u8) },
+}
+
+/// Why a discovered plugin was not mounted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Mounting it would have exceeded [`MountOptions::memory_budget`].
+    OverBudget,
+    /// Its hash was rejected by `RegisterModuleInfo`, and it was dropped so the rest of the
+    /// plugins could be registered. See [`MountOptions::registration_retries`].
+    RejectedByNrr { rc: u32 },
+    /// An identical module was already loaded, and [`AlreadyLoadedPolicy::Skip`] was set.
+    AlreadyLoaded,
+    /// Its hash was already registered by an earlier mount sharing the same
+    /// [`MountOptions::registry`], which mounted it. Like [`SkipReason::Hidden`], this doesn't fail
+    /// strict mode.
+    AlreadyRegistered,
+    /// It was mounted, then unloaded again to make room for a later plugin, see
+    /// [`MountOptions::on_out_of_address_space`].
+    Shed,
+    /// [`MountOptions::filter_meta`] rejected it.
+    FilteredByMeta,
+    /// Its file name starts with a `.`, see [`MountOptions::include_hidden`]. Unlike other
+    /// skipped plugins, these don't fail strict mode.
+    Hidden,
+    /// It's the same file as an earlier plugin, reached again through a symlink (or by being
+    /// listed twice, e.g. from a directory which is also in [`MountOptions::directories`]).
+    SymlinkCycle,
+    /// It was in a [`PluginSnapshot`] being restored, but its file no longer exists.
+    Missing,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OverBudget => write!(f, "memory budget exceeded"),
+            Self::AlreadyLoaded => write!(f, "already loaded"),
+            Self::AlreadyRegistered => write!(f, "already registered by an earlier mount"),
+            Self::Shed => write!(f, "unloaded to make room for another plugin"),
+            Self::FilteredByMeta => write!(f, "rejected by metadata filter"),
+            Self::Hidden => write!(f, "hidden file"),
+            Self::SymlinkCycle => write!(f, "same file as an earlier plugin"),
+            Self::Missing => write!(f, "file no longer exists"),
+            Self::RejectedByNrr { rc } => write!(f, "rejected during registration: {}", ResultCode(*rc)),
+        }
+    }
+}
+
+/// A non-fatal issue encountered while mounting. Most become errors in strict mode, see
+/// [`MountOptions::strict`].
+#[derive(Debug)]
+pub enum Diagnostic {
+    Skipped { path: PathBuf, reason: SkipReason },
+    /// A name passed to [`MountOptions::bootstrap`] didn't match any discovered plugin.
+    UnmatchedBootstrap { name: String },
+    /// A hash passed to [`MountOptions::extra_hashes`] was dropped by
+    /// [`MountOptions::registration_retries`].
+    ExtraHashRejected { hash: Sha256Hash, rc: u32 },
+    /// A module's BSS was allocated larger than `GetBufferSize` reported because of
+    /// [`MountOptions::bss_headroom`]. This is informational and doesn't fail strict mode.
+    BssPadded { name: String, reported: usize, allocated: usize },
+}
+
+impl Diagnostic {
+    /// The error this fails a strict mount with, or `None` if it's only informational.
+    fn into_error(self) -> Option<LoaderError> {
+        match self {
+            Self::Skipped { path, reason } => Some(LoaderError::StrictModeViolation {
+                path,
+                source: Box::new(LoaderError::Skipped(reason))
+            }),
+            Self::UnmatchedBootstrap { name } => Some(LoaderError::UnmatchedBootstrap { name }),
+            Self::ExtraHashRejected { rc, .. } => Some(LoaderError::RegistrationError(rc)),
+            Self::BssPadded { .. } => None,
+        }
+    }
+}
+
+/// The order plugins are registered and mounted in.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum LoadOrder {
+    /// The order the directory listing returned them in.
+    #[default]
+    Discovery,
+    /// Sorted by file name.
+    FileName,
+    /// The named plugins first, in the given sequence, followed by the rest in discovery order.
+    /// Names are matched like [`MountOptions::bootstrap`], and names which don't match anything
+    /// are ignored. See [`MountInfo::preferred_order`].
+    Explicit(Vec<String>),
+}
+
+/// What to do with module names which don't fit in `Module.Name`. See
+/// [`MountOptions::name_policy`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum NamePolicy {
+    /// Fail the module with [`LoaderError::NameTooLong`].
+    #[default]
+    Reject,
+    /// Cut the name at the last character boundary that fits, so it stays valid UTF-8.
+    Truncate,
+    /// Replace every non-ASCII character with `_`, then truncate.
+    Ascii,
+}
+
+/// The priority of a plugin directory. When plugins override each other, the one from the higher
+/// priority directory wins.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Priority(pub i32);
+
+/// Decides which plugins from different directories count as "the same plugin", in which case only
+/// the one from the highest priority directory is mounted.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OverridePolicy {
+    /// Plugins with the same file name override each other.
+    #[default]
+    ByName,
+    /// Plugins with identical contents override each other.
+    ByHash,
+    /// Every plugin is mounted.
+    None,
+}
+
+fn plugin_matches(path: &Path, name: &str) -> bool {
+    let name = Some(std::ffi::OsStr::new(name));
+    path.file_name() == name || path.file_stem() == name
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Sha256Hash([u8; 0x20]);
+
+impl std::fmt::Display for Sha256Hash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-struct Sha256Hash([u8; 0x20]);
+impl std::fmt::Debug for Sha256Hash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Sha256Hash({})", self)
+    }
+}
 
 impl Sha256Hash {
+    pub fn as_bytes(&self) -> &[u8; 0x20] {
+        &self.0
+    }
+
     pub fn new(data: &[u8]) -> Self {
-        let mut hash = [0u8; 0x20];
+        Self(ro::sha256(data))
+    }
+}
+
+/// A registered NRR image. The image has to stay alive for as long as it is registered, so it is
+/// owned here and only freed once [`Registration::unregister`] is called.
+pub struct Registration {
+    info: nn::ro::RegistrationInfo,
+    image: *mut u8,
+    layout: Layout,
+    program_id: u64,
+    hashes: Vec<Sha256Hash>,
+    // Set for NRRs registered by a mount with `MountOptions::registry`
+    registry: Option<Arc<Registry>>,
+}
+
+impl Registration {
+    pub fn program_id(&self) -> u64 {
+        self.program_id
+    }
+
+    /// The size of the NRR image, including the header.
+    pub fn image_size(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// The hashes in this NRR, in the order they were written to the image.
+    pub fn hashes(&self) -> &[Sha256Hash] {
+        &self.hashes
+    }
+
+    pub fn unregister(self) -> Result<(), LoaderError> {
+        let Self { mut info, image, layout, hashes, registry, .. } = self;
         unsafe {
-            nn::crypto::GenerateSha256Hash(hash.as_mut_ptr() as _, 0x20, data.as_ptr() as _, data.len() as u64);
+            let rc = ro::unregister_module_info(&mut info);
+            if rc != 0 {
+                return Err(LoaderError::UnregistrationError(rc));
+            }
+            std::alloc::dealloc(image, layout);
         }
-        Self(hash)
+        if let Some(registry) = registry {
+            registry.remove(&hashes);
+        }
+        Ok(())
     }
 }
 
-struct NroFile {
-    data: Vec<u8>,
+/// Describes a module to the [`MountOptions::on_module_loaded`] and
+/// [`MountOptions::on_module_unloaded`] hooks.
+pub struct ModuleInfo<'a> {
+    pub name: &'a str,
+    pub path: Option<&'a Path>,
+    pub hash: Sha256Hash,
+    /// The address the module is mapped at.
+    pub base: usize,
+}
+
+type ModuleHook = Arc<Mutex<dyn FnMut(&ModuleInfo) + Send>>;
+type HashHook = Arc<Mutex<dyn FnMut(&Path, &Sha256Hash) + Send>>;
+type RenameHook = Arc<dyn Fn(&Path) -> Option<String> + Send + Sync>;
+type MetaFilter = Arc<dyn Fn(Option<&PluginMeta>) -> bool + Send + Sync>;
+type ShedHook = Arc<dyn Fn(&[ModuleInfo]) -> Option<String> + Send + Sync>;
+
+// Modules keep a copy of the hooks they were mounted with, so unloading one later on (including
+// as part of a reload) still notifies the consumer.
+#[derive(Clone, Default)]
+struct Hooks {
+    loaded: Option<ModuleHook>,
+    unloaded: Option<ModuleHook>,
+    hashed: Option<HashHook>,
+}
+
+impl Hooks {
+    fn fire(hook: &Option<ModuleHook>, module: &LoadedModule) {
+        if let Some(hook) = hook {
+            let mut hook = hook.lock().unwrap_or_else(PoisonError::into_inner);
+            hook(&module.info());
+        }
+    }
+
+    fn fire_hashed(&self, plugin: &PreparedPlugin) {
+        if let (Some(hook), Ok((_, hash))) = (&self.hashed, &plugin.nro) {
+            let mut hook = hook.lock().unwrap_or_else(PoisonError::into_inner);
+            hook(&plugin.path, hash);
+        }
+    }
+}
+
+/// A module which has been mounted with `nn::ro::LoadModule`, along with the buffers backing it.
+pub struct LoadedModule {
+    module: Module,
     name: String,
+    path: Option<PathBuf>,
+    hash: Sha256Hash,
+    image: *mut u8,
+    image_layout: Layout,
+    // The size declared in the NRO header, which is how much of the image `nn::ro` maps. The
+    // buffer may be larger, e.g. when it's reused by `reload_in_place`.
+    mapped_size: usize,
+    // Images passed to `mount_from_mapped` belong to the caller and are never freed here
+    owns_image: bool,
+    bss: *mut u8,
+    bss_layout: Layout,
+    // BSS buffers passed to `mount_with_bss` belong to the caller and are never freed here
+    owns_bss: bool,
+    // What `GetBufferSize` asked for, which the allocation may have been padded past
+    bss_size: usize,
+    mount_duration: Option<Duration>,
+    // Where the image (if owned) and BSS came from, and have to be freed to
+    allocator: Arc<dyn Allocator + Send + Sync>,
+    // The single-module NRR created when this module was reloaded or mounted with
+    // `mount_from_mapped`. Modules mounted as part of a directory share the NRRs in `MountInfo` and
+    // do not own one.
+    registration: Option<Registration>,
+    hooks: Hooks,
+    settings: LoadSettings,
+    reader: Reader,
 }
 
-impl NroFile {
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, LoaderError> {
-        let path = path.as_ref();
-        std::fs::read(path)
-            .map(|data| Self { data, name: path.file_name().unwrap().to_string_lossy().to_string() })
-            .map_err(Into::into)
+impl LoadedModule {
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
-    pub fn fix_bss_size(&mut self) {
-        unsafe {
-            // Get the mod header offset
-            let mod_header_offset = *(self.data.as_ptr().add(4) as *const u32);
+    /// The file this module was read from, if it was mounted from one.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
 
-            let mod_header = self.data.as_mut_ptr().add(mod_header_offset as usize + 0x18) as *mut u32;
+    /// The SHA-256 hash of the image this module was mounted from, as registered in its NRR.
+    pub fn hash(&self) -> Sha256Hash {
+        self.hash
+    }
 
-            let bss_end_offset = *mod_header.add(3);
-            let module_object_offset = *mod_header.add(7);
-            if bss_end_offset == module_object_offset {
-                *mod_header.add(3) += 0xD0;
-            }
+    /// The underlying `nn::ro::Module`, for passing to other SDK functions.
+    pub fn as_nn_module(&self) -> &Module {
+        &self.module
+    }
+
+    /// The underlying `nn::ro::Module`, for SDK functions which take a `*mut Module`.
+    ///
+    /// This wrapper still unloads the module and frees its buffers, so anything which changes the
+    /// module's state behind its back (e.g. calling `nn::ro::UnloadModule` on it) breaks that, and
+    /// unloading it afterwards is undefined behavior.
+    pub fn as_nn_module_mut(&mut self) -> &mut Module {
+        &mut self.module
+    }
+
+    /// Looks up the address of an exported symbol of this module.
+    ///
+    /// Fails with [`LoaderError::SymbolNotFound`] if the module doesn't export `name`, with
+    /// [`LoaderError::InvalidSymbolName`] if `name` is empty or contains a NUL, and with
+    /// [`LoaderError::LookupError`] if `nn::ro` couldn't do the lookup at all.
+    pub fn lookup_symbol(&self, name: &str) -> Result<*const (), LoaderError> {
+        let symbol = symbol_name(name)?;
+        let mut address = 0;
+        let rc = unsafe { ro::lookup_module_symbol(&mut address, &self.module, symbol.as_ptr()) };
+        lookup_result(name, rc, address)
+    }
+
+    /// Looks up every symbol in `names`, returning their addresses in the same order, e.g. to check
+    /// a plugin exports everything it's expected to right after mounting it. Fails on the first
+    /// symbol which can't be found, like [`lookup_symbol`](Self::lookup_symbol), in which case
+    /// nothing is returned.
+    pub fn resolve_all(&self, names: &[&str]) -> Result<Vec<*const ()>, LoaderError> {
+        resolve_with(names, |name| self.lookup_symbol(name))
+    }
+
+    /// Like [`lookup_symbol`](Self::lookup_symbol), for callers which don't care why a symbol
+    /// couldn't be found.
+    pub fn symbol(&self, name: &str) -> Option<*const ()> {
+        self.lookup_symbol(name).ok()
+    }
+
+    pub fn info(&self) -> ModuleInfo<'_> {
+        ModuleInfo {
+            name: &self.name,
+            path: self.path.as_deref(),
+            hash: self.hash,
+            base: self.base() as usize,
         }
     }
 
-    pub fn hash(&self) -> Sha256Hash {
-        Sha256Hash::new(&self.data)
+    fn base(&self) -> *const u8 {
+        unsafe { (*self.module.ModuleObject).module_base as *const u8 }
     }
 
-    pub fn mount(self) -> Result<Module, LoaderError> {
-        use std::alloc;
+    /// The module as mapped by `nn::ro`, which is the size declared in its NRO header.
+    fn mapped(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.base(), self.mapped_size) }
+    }
 
-        let Self { data, name } = self;
+    /// Returns the `(tag, value)` pairs of the module's `.dynamic` section, up to but not
+    /// including the terminating `DT_NULL`. Modules without a dynamic section return nothing.
+    pub fn dynamic_entries(&self) -> Vec<(u64, u64)> {
+        nro::parse(self.mapped())
+            .map(|view| view.dynamic_entries())
+            .unwrap_or_default()
+    }
 
-        let layout = alloc::Layout::from_size_align(data.len(), 0x1000).unwrap();
-        let image = unsafe {
-            let memory = alloc::alloc(layout);
-            std::ptr::copy_nonoverlapping(data.as_ptr(), memory, data.len());
-            drop(data);
-            memory
-        };
+    /// The address range of the module's `.eh_frame_hdr` section, if it has one.
+    pub fn eh_frame_hdr_range(&self) -> Option<Range<usize>> {
+        let base = self.base() as usize;
+        let range = nro::parse(self.mapped()).ok()?.eh_frame_hdr_range()?;
+        Some(base + range.start..base + range.end)
+    }
 
-        let bss_size = unsafe {
-            let mut size = 0;
-            let rc = nn::ro::GetBufferSize(&mut size, image as _);
+    /// The address range of the module's `.eh_frame` section, located through `.eh_frame_hdr`.
+    ///
+    /// Plugins which unwind across the FFI boundary need this registered with the unwinder, i.e.
+    /// by passing `eh_frame_range().start` to `__register_frame`, and deregistering it before the
+    /// module is unloaded (see [`MountOptions::on_module_unloaded`]).
+    pub fn eh_frame_range(&self) -> Option<Range<usize>> {
+        let base = self.base() as usize;
+        let range = nro::parse(self.mapped()).ok()?.eh_frame_range()?;
+        Some(base + range.start..base + range.end)
+    }
+
+    /// The `(start, len)` of the module's image where it's mapped, for attributing addresses to
+    /// this module. This isn't the buffer the image was loaded from, which `nn::ro` takes over.
+    pub fn image_range(&self) -> (usize, usize) {
+        (self.base() as usize, self.mapped_size)
+    }
+
+    /// The `(start, len)` of the module's BSS where it's mapped, directly after the image.
+    pub fn bss_range(&self) -> (usize, usize) {
+        (self.base() as usize + align_up!(self.mapped_size, 0x1000), self.bss_size)
+    }
+
+    /// The memory used by the module's image and BSS buffers, each rounded up to whole pages.
+    pub fn footprint(&self) -> usize {
+        align_up!(self.image_layout.size(), 0x1000) + align_up!(self.bss_layout.size(), 0x1000)
+    }
+
+    /// How long `LoadModule` took for this module, including relocation. `None` unless it was
+    /// mounted with [`MountOptions::timings`].
+    pub fn mount_duration(&self) -> Option<Duration> {
+        self.mount_duration
+    }
+
+    /// The metadata the plugin embedded, if any. See [`PluginMeta`] for the format.
+    pub fn meta(&self) -> Option<PluginMeta> {
+        nro::parse(self.mapped()).ok()?.plugin_meta()
+    }
+
+    /// The module's GNU build id, for matching crash reports up with the build's debug symbols.
+    /// `None` if it was linked without one.
+    pub fn build_id(&self) -> Option<&[u8]> {
+        nro::parse(self.mapped()).ok()?.build_id()
+    }
+
+    /// Checks that the module was mapped with the permissions its segments call for: text `r-x`,
+    /// rodata `r--`, and data and BSS `rw-`. Every page of each segment is covered, and the first
+    /// region which doesn't match is reported as [`LoaderError::PermissionMismatch`].
+    pub fn verify_permissions(&self) -> Result<(), LoaderError> {
+        let view = nro::parse(self.mapped())?;
+        let base = self.base() as usize;
+        let segments = [
+            ("text", view.text.offset, view.text.size, MemoryPermission::READ_EXECUTE),
+            ("ro", view.ro.offset, view.ro.size, MemoryPermission::READ),
+            ("data", view.data_segment.offset, view.data_segment.size, MemoryPermission::READ_WRITE),
+            ("bss", self.bss_range().0 - base, self.bss_range().1, MemoryPermission::READ_WRITE),
+        ];
+
+        for (segment, offset, size, expected) in segments {
+            let (mut address, end) = (base + offset, base + offset + size);
+            while address < end {
+                let info = svc::query_memory(address).ok_or(LoaderError::MemoryQueryUnsupported)?;
+                let actual = MemoryPermission(info.permission);
+                if actual != expected {
+                    return Err(LoaderError::PermissionMismatch { segment, address, expected, actual });
+                }
+                address = ((info.address + info.size) as usize).max(address + 0x1000);
+            }
+        }
+        Ok(())
+    }
+
+    /// Unloads the module, frees its buffers, and unregisters the NRR it owns (if any). The
+    /// [`MountOptions::on_module_unloaded`] hook runs first, while the module is still mapped.
+    pub fn unload(self) -> Result<(), LoaderError> {
+        Hooks::fire(&self.hooks.unloaded, &self);
+
+        let Self { mut module, image, image_layout, owns_image, bss, bss_layout, owns_bss, allocator, registration, .. } = self;
+        unsafe {
+            let rc = ro::unload_module(&mut module);
             if rc != 0 {
-                alloc::dealloc(image, layout);
-                return Err(LoaderError::InvalidModuleBuffer(rc));
+                return Err(LoaderError::UnloadError(rc));
             }
-            size as usize
-        };
+            if owns_image {
+                free(&*allocator, image, image_layout);
+            }
+            if owns_bss {
+                free(&*allocator, bss, bss_layout);
+            }
+        }
+
+        match registration {
+            Some(registration) => registration.unregister(),
+            None => Ok(())
+        }
+    }
 
-        let bss_layout = alloc::Layout::from_size_align(bss_size, 0x1000).unwrap();
+    /// Re-reads the module and mounts it again. Modules from a directory are re-read the way they
+    /// were read originally, i.e. through the same [`PluginSource`] and transform, and every module
+    /// is mounted with the same BSS, name and timing options as the first time.
+    ///
+    /// NRRs are immutable, so the new version gets its own single-module NRR. It's registered
+    /// before the old version is unloaded, which unregisters the NRR created by a previous reload,
+    /// so a module never holds more than one registration no matter how many times it is reloaded.
+    ///
+    /// The old version has to be unloaded before the new one can be loaded, so on failure it's
+    /// gone either way: if the file can't be read or the new NRR can't be registered, the old
+    /// version is unloaded before the error is returned, and if the new version fails to load, its
+    /// NRR is unregistered again. Nothing of the module is left loaded or registered.
+    pub fn reload(self, program_id: u64) -> Result<Self, LoaderError> {
+        let new = self.path.as_ref().ok_or(LoaderError::NotReloadable).and_then(|path| {
+            let (mut nro, hash) = NroFile::prepare(&self.reader, path)?;
+            nro.name = self.name.clone();
+            check_name(&nro.name, self.settings.name_policy)?;
+            Ok((nro, hash, own_nrr(program_id, hash, self.settings.nrr_kind)?))
+        });
 
-        let bss_memory = unsafe {
-            alloc::alloc(layout)
+        let options = self.settings.options(self.hooks.clone(), self.allocator.clone());
+        let reader = self.reader.clone();
+        let (nro, hash, registration) = match (new, self.unload()) {
+            (Ok(new), Ok(())) => new,
+            (Ok((_, _, registration)), Err(error)) => {
+                let _ = registration.unregister();
+                return Err(error);
+            },
+            (Err(error), _) => return Err(error),
         };
 
-        unsafe {
-            let mut module: Module = std::mem::MaybeUninit::zeroed().assume_init();
-            module.Name[0..name.len()].copy_from_slice(name.as_bytes());
-            
-            let rc = nn::ro::LoadModule(
-                &mut module,
-                image as _,
-                bss_memory as _,
-                bss_size as u64,
-                nn::ro::BindFlag_BindFlag_Lazy as i32
-            );
+        let mut module = mount_registered(nro, hash, &options, registration)?;
+        module.reader = reader;
+        Ok(module)
+    }
 
-            if rc != 0 {
-                alloc::dealloc(image, layout);
-                alloc::dealloc(bss_memory, bss_layout);
+    /// Experimental: replaces the module with `bytes`, reusing its image and BSS buffers instead
+    /// of allocating new ones. Like [`LoadedModule::reload`], the new image gets its own
+    /// single-module NRR under `program_id`.
+    ///
+    /// The new image can't be larger than the old one, or this fails with
+    /// [`LoaderError::ImageTooLarge`], and its BSS has to fit in the old BSS buffer, or this fails
+    /// with [`LoaderError::BssTooSmall`]. Both are checked before anything is unloaded. Modules
+    /// whose image belongs to the caller (see [`mount_from_mapped`]) fail with
+    /// [`LoaderError::NotReloadable`].
+    ///
+    /// `nn::ro` picks where a module is mapped every time it's loaded, so reusing the buffers does
+    /// *not* keep the base address: compare [`LoadedModule::image_range`] before and after. Any
+    /// pointer into the old version is dangling afterwards, and code still running from it (e.g.
+    /// on another thread, or a hook it installed) will crash. If loading the new image fails, the
+    /// old version is already gone and its buffers are freed.
+    pub fn reload_in_place(mut self, bytes: &[u8], program_id: u64) -> Result<Self, LoaderError> {
+        if !self.owns_image {
+            return Err(LoaderError::NotReloadable);
+        }
+        let view = nro::parse(bytes)?;
+        if bytes.len() > self.image_layout.size() {
+            return Err(LoaderError::ImageTooLarge { size: bytes.len(), capacity: self.image_layout.size() });
+        }
+        if view.bss_size > self.bss_layout.size() {
+            return Err(LoaderError::BssTooSmall { required: view.bss_size, provided: self.bss_layout.size() });
+        }
+        check_name(&self.name, self.settings.name_policy)?;
+
+        Hooks::fire(&self.hooks.unloaded, &self);
+        let rc = unsafe { ro::unload_module(&mut self.module) };
+        if rc != 0 {
+            return Err(LoaderError::UnloadError(rc));
+        }
 
-                Err(LoaderError::MountError(rc))
-            } else {
+        let Self {
+            name, path, image, image_layout, bss, bss_layout, owns_bss, allocator, registration, hooks, reader, settings, ..
+        } = self;
+        let free_bss = || if owns_bss { unsafe { free(&*allocator, bss, bss_layout) } };
+        let hash = Sha256Hash::new(bytes);
+        let registered = registration.map_or(Ok(()), Registration::unregister)
+            .and_then(|_| register_built(&NrrBuilder::new(program_id).kind(settings.nrr_kind).hashes(&[hash])));
+        let registration = match registered {
+            Ok(registration) => registration,
+            Err(e) => {
+                unsafe { free(&*allocator, image, image_layout) };
+                free_bss();
+                return Err(e);
+            }
+        };
+
+        let options = settings.options(hooks, allocator.clone());
+        let loaded = unsafe {
+            let buffer = std::slice::from_raw_parts_mut(image, image_layout.size());
+            buffer[..bytes.len()].copy_from_slice(bytes);
+            buffer[bytes.len()..].fill(0);
+            // A module without a BSS has no buffer to reuse
+            let bss: &mut [u8] = if bss.is_null() { &mut [] } else { std::slice::from_raw_parts_mut(bss, bss_layout.size()) };
+            load_image(image, image_layout, true, Some(bss), name, path, hash, &options)
+        };
+        match loaded {
+            Ok(mut module) => {
+                module.owns_bss = owns_bss;
+                module.registration = Some(registration);
+                module.reader = reader;
                 Ok(module)
+            },
+            Err(e) => {
+                free_bss();
+                let _ = registration.unregister();
+                Err(e)
             }
         }
     }
 }
 
-pub struct MountInfo {
-    pub modules: Vec<Result<nn::ro::Module, LoaderError>>,
-    pub registration_info: nn::ro::RegistrationInfo,
+/// Registers a single-module NRR for `nro` and mounts it, handing the NRR to the module.
+fn mount_with_own_nrr(program_id: u64, nro: NroFile, hash: Sha256Hash, options: &MountOptions) -> Result<LoadedModule, LoaderError> {
+    let registration = own_nrr(program_id, hash, options.nrr_kind)?;
+    mount_registered(nro, hash, options, registration)
 }
 
-pub fn mount_from_directory<P: AsRef<Path>, F: Fn(&Path) -> bool>(program_id: u64, path: P, validator: F) -> Result<MountInfo, LoaderError> {
-    use std::alloc;
-    let mut plugins = Vec::new();
-    for entry in std::fs::read_dir(path)? {
-        let Ok(entry) = entry else { continue };
-        let path = entry.path();
-        if !validator(&path) { continue };
-
-        plugins.push(NroFile::open(&path).map(|mut nro| { nro.fix_bss_size(); nro }));
-    }
-
-    // Handle creating the raw NRR image
-    let registration_info = {
-        let num_modules = plugins.iter()
-            .filter(|plugin| plugin.is_ok())
-            .count();
-
-        let image_size = align_up!(
-            std::mem::size_of::<nn::ro::NrrHeader>() + num_modules * std::mem::size_of::<Sha256Hash>(),
-            0x1000
-        );
-        
-
-        let (header, shas) = unsafe {
-            let layout = alloc::Layout::from_size_align(image_size, 0x1000).unwrap();
-            let memory = alloc::alloc_zeroed(layout);
-            (
-                &mut *(memory as *mut NrrHeader),
-                std::slice::from_raw_parts_mut(
-                    memory.add(std::mem::size_of::<NrrHeader>()) as *mut Sha256Hash, 
-                    num_modules
-                )
-            )
-        };
+/// Registers a single-module NRR for `hash`.
+fn own_nrr(program_id: u64, hash: Sha256Hash, kind: NrrKind) -> Result<Registration, LoaderError> {
+    register_built(&NrrBuilder::new(program_id).kind(kind).hashes(&[hash]))
+}
+
+/// Mounts `nro`, which `registration` was registered for, and hands the registration to the
+/// module. If mounting fails, it's unregistered again.
+fn mount_registered(nro: NroFile, hash: Sha256Hash, options: &MountOptions, registration: Registration) -> Result<LoadedModule, LoaderError> {
+    match nro.mount(hash, options) {
+        Ok(mut module) => {
+            module.registration = Some(registration);
+            Ok(module)
+        },
+        Err(e) => {
+            let _ = registration.unregister();
+            Err(e)
+        }
+    }
+}
+
+type TransformHook = Arc<dyn Fn(&Path, Vec<u8>) -> Result<Vec<u8>, LoaderError> + Send + Sync>;
+type Decompressor = Arc<dyn Fn(&[u8]) -> Result<Vec<u8>, LoaderError> + Send + Sync>;
+
+/// How each module was loaded, taken out of [`MountOptions`] so reloading the module loads it the
+/// same way.
+#[derive(Copy, Clone)]
+struct LoadSettings {
+    retain_failed_buffers: bool,
+    verify_on_mount: bool,
+    bss_align: usize,
+    bss_headroom: usize,
+    timings: bool,
+    flush_icache: bool,
+    name_policy: NamePolicy,
+    nrr_kind: NrrKind,
+}
+
+impl LoadSettings {
+    /// Options which mount like the ones these settings were taken from.
+    fn options(self, hooks: Hooks, allocator: Arc<dyn Allocator + Send + Sync>) -> MountOptions {
+        MountOptions {
+            retain_failed_buffers: self.retain_failed_buffers,
+            verify_on_mount: self.verify_on_mount,
+            bss_align: self.bss_align,
+            bss_headroom: self.bss_headroom,
+            timings: self.timings,
+            flush_icache: self.flush_icache,
+            name_policy: self.name_policy,
+            nrr_kind: self.nrr_kind,
+            hooks,
+            allocator,
+            ..MountOptions::default()
+        }
+    }
+}
+
+/// How plugins are read, taken out of [`MountOptions`] so it can be moved to another thread or kept
+/// around for reloading.
+#[derive(Clone)]
+struct Reader {
+    source: Arc<dyn PluginSource + Send + Sync>,
+    timeout: Option<Duration>,
+    transform: Option<TransformHook>,
+    decompress: Option<Decompressor>,
+}
 
+impl Default for Reader {
+    fn default() -> Self {
+        Self { source: Arc::new(StdFs), timeout: None, transform: None, decompress: None }
+    }
+}
 
-        header.magic = 0x3052524E;
-        header.program_id = nn::ro::ProgramId { value: program_id };
-        header.size = image_size as u32;
-        header.type_ = 0;
-        header.hashes_offset = std::mem::size_of::<NrrHeader>() as u32;
-        header.num_hashes = num_modules as u32;
+#[derive(Clone)]
+struct NroFile {
+    data: Vec<u8>,
+    name: String,
+    path: Option<PathBuf>,
+}
 
-        for (count, file) in plugins.iter().filter(|plugin| plugin.is_ok()).enumerate() {
-            shas[count] = file.as_ref().unwrap().hash();
+impl NroFile {
+    pub fn open<P: AsRef<Path>>(reader: &Reader, path: P) -> Result<Self, LoaderError> {
+        let path = path.as_ref();
+        let mut data = reader.source.read(path, reader.timeout)?;
+        if let Some(transform) = &reader.transform {
+            data = transform(path, data)?;
+        }
+        if let Some(decompress) = &reader.decompress {
+            if !nro::has_magic(&data) {
+                data = decompress(&data)?;
+            }
         }
 
-        shas.sort();
+        Ok(Self {
+            data,
+            name: path.file_name().unwrap().to_string_lossy().to_string(),
+            path: Some(path.to_path_buf())
+        })
+    }
+
+    /// Reads the file, validates it, fixes up its BSS size and hashes it. This is all of the work
+    /// that has to happen before registration, none of which touches `nn::ro`.
+    pub fn prepare<P: AsRef<Path>>(reader: &Reader, path: P) -> Result<(Self, Sha256Hash), LoaderError> {
+        let mut nro = Self::open(reader, path)?;
+        nro::parse(&nro.data)?;
+        nro.fix_bss_size()?;
+        let hash = nro.hash();
+        Ok((nro, hash))
+    }
+
+    pub fn fix_bss_size(&mut self) -> Result<(), LoaderError> {
+        nro::fix_bss_size(&mut self.data)
+    }
+
+    /// Estimates the memory needed to mount this file: the page-aligned image plus the
+    /// page-aligned BSS size declared in the NRO header.
+    pub fn footprint(&self) -> usize {
+        let bss_size = nro::parse(&self.data).map(|view| view.bss_size).unwrap_or(0);
+        align_up!(self.data.len(), 0x1000) + align_up!(bss_size, 0x1000)
+    }
+
+    pub fn hash(&self) -> Sha256Hash {
+        Sha256Hash::new(&self.data)
+    }
 
+    /// The BSS size `GetBufferSize` asks for, before any [`MountOptions::bss_headroom`].
+    pub fn buffer_size(&self) -> Result<usize, LoaderError> {
+        // It's given a page-aligned copy, like the one which is mounted
+        let layout = Layout::from_size_align(self.data.len(), 0x1000).unwrap();
         unsafe {
-            let mut nrr_info = std::mem::MaybeUninit::uninit();
-            let rc = nn::ro::RegisterModuleInfo(nrr_info.as_mut_ptr(), header as *mut NrrHeader as _);
-            if rc != 0 {
-                let layout = alloc::Layout::from_size_align(image_size, 0x1000).unwrap();
-                alloc::dealloc(header as *mut NrrHeader as _, layout);
-                return Err(LoaderError::RegistrationError(rc));
+            let image = allocate(&Heap, layout)?;
+            std::ptr::copy_nonoverlapping(self.data.as_ptr(), image, self.data.len());
+            let mut size = 0;
+            let rc = ro::get_buffer_size(&mut size, image);
+            free(&Heap, image, layout);
+            match rc {
+                0 => Ok(size),
+                rc => Err(LoaderError::InvalidModuleBuffer(rc)),
             }
-            nrr_info.assume_init()
         }
-    };
+    }
 
-    let modules = plugins
-        .into_iter()
-        .map(|plugin| plugin.and_then(NroFile::mount))
-        .collect();
-    
+    pub fn meta(&self) -> Option<PluginMeta> {
+        nro::parse(&self.data).ok()?.plugin_meta()
+    }
 
-    Ok(MountInfo {
-        modules,
-        registration_info
+    pub fn mount(self, hash: Sha256Hash, options: &MountOptions) -> Result<LoadedModule, LoaderError> {
+        unsafe { self.mount_with_bss(hash, options, None) }
+    }
+
+    /// # Safety
+    /// See [`load_image`].
+    unsafe fn mount_with_bss(self, hash: Sha256Hash, options: &MountOptions, bss: Option<&mut [u8]>) -> Result<LoadedModule, LoaderError> {
+        use std::alloc;
+
+        let Self { data, name, path } = self;
+        check_name(&name, options.name_policy)?;
+
+        let layout = alloc::Layout::from_size_align(data.len(), 0x1000).unwrap();
+        unsafe {
+            let image = allocate(&*options.allocator, layout)?;
+            std::ptr::copy_nonoverlapping(data.as_ptr(), image, data.len());
+            drop(data);
+            load_image(image, layout, true, bss, name, path, hash, options)
+        }
+    }
+}
+
+/// The size of `Module.Name`, which holds a null-terminated string.
+const MODULE_NAME_SIZE: usize = 0x100;
+
+/// Turns `name` into what's stored in `Module.Name`, which has to fit along with its null
+/// terminator. Depending on `policy` an overlong name fails its own module, rather than overrunning
+/// the buffer, or is cut on a character boundary so it can be read back as UTF-8.
+fn module_name(name: &str, policy: NamePolicy) -> Result<Cow<'_, str>, LoaderError> {
+    let name = match policy {
+        NamePolicy::Ascii if !name.is_ascii() => {
+            Cow::Owned(name.chars().map(|c| if c.is_ascii() { c } else { '_' }).collect())
+        },
+        _ => Cow::Borrowed(name),
+    };
+    if name.len() < MODULE_NAME_SIZE {
+        return Ok(name);
+    }
+    if policy == NamePolicy::Reject {
+        return Err(LoaderError::NameTooLong { name: name.into_owned(), max: MODULE_NAME_SIZE - 1 });
+    }
+
+    let mut end = MODULE_NAME_SIZE - 1;
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    Ok(match name {
+        Cow::Borrowed(name) => Cow::Borrowed(&name[..end]),
+        Cow::Owned(mut name) => {
+            name.truncate(end);
+            Cow::Owned(name)
+        },
     })
-}
\ No newline at end of file
+}
+
+/// Checks that `name` can be stored in `Module.Name` under `policy`, see [`module_name`].
+fn check_name(name: &str, policy: NamePolicy) -> Result<(), LoaderError> {
+    module_name(name, policy).map(drop)
+}
+
+/// Allocates a buffer for a single module. Failure is reported as [`LoaderError::OutOfMemory`] so
+/// that it only fails that module. Zero-sized layouts (i.e. a module without a BSS) give a null
+/// pointer, which `nn::ro` accepts when the size is zero.
+fn allocate(allocator: &dyn Allocator, layout: Layout) -> Result<*mut u8, LoaderError> {
+    if layout.size() == 0 {
+        return Ok(std::ptr::null_mut());
+    }
+
+    let memory = allocator.allocate(layout);
+    if memory.is_null() {
+        return Err(LoaderError::OutOfMemory { size: layout.size() });
+    }
+    Ok(memory)
+}
+
+/// Frees a buffer from [`allocate`].
+///
+/// # Safety
+/// `memory` must have come from [`allocate`] with the same `allocator` and `layout`.
+unsafe fn free(allocator: &dyn Allocator, memory: *mut u8, layout: Layout) {
+    if layout.size() != 0 {
+        allocator.free(memory, layout);
+    }
+}
+
+/// Loads the NRO in `image`, allocating its BSS unless `bss` is given. `image` is freed on failure
+/// or when the module is unloaded if `owns_image` is set, and left alone otherwise. A given `bss`
+/// is never freed.
+///
+/// # Safety
+/// `image` must be valid for `layout`, and it and `bss` must stay valid for as long as the module
+/// is loaded.
+#[allow(clippy::too_many_arguments)]
+unsafe fn load_image(
+    image: *mut u8,
+    layout: Layout,
+    owns_image: bool,
+    bss: Option<&mut [u8]>,
+    name: String,
+    path: Option<PathBuf>,
+    hash: Sha256Hash,
+    options: &MountOptions
+) -> Result<LoadedModule, LoaderError> {
+    use std::alloc;
+
+    let allocator = &*options.allocator;
+    let free_image = || if owns_image { free(allocator, image, layout) };
+
+    let mut module: Module = std::mem::MaybeUninit::zeroed().assume_init();
+    let module_name = match module_name(&name, options.name_policy) {
+        Ok(module_name) => module_name.into_owned().into_bytes(),
+        Err(e) => {
+            free_image();
+            return Err(e);
+        }
+    };
+
+    // `nn::ro` validates the header again and fails the load if it's malformed
+    let mapped_size = nro::parse(std::slice::from_raw_parts(image, layout.size()))
+        .map_or(layout.size(), |view| view.size);
+
+    if options.verify_on_mount {
+        let actual = Sha256Hash::new(std::slice::from_raw_parts(image, layout.size()));
+        if actual != hash {
+            free_image();
+            return Err(LoaderError::HashMismatch { expected: hash, actual });
+        }
+    }
+
+    let bss_size = {
+        let mut size = 0;
+        let rc = ro::get_buffer_size(&mut size, image);
+        if rc != 0 {
+            free_image();
+            return Err(LoaderError::InvalidModuleBuffer(rc));
+        }
+        size
+    };
+
+    let (bss_memory, bss_layout, owns_bss, loaded_size) = match bss {
+        Some(bss) => {
+            if bss.len() < bss_size {
+                free_image();
+                return Err(LoaderError::BssTooSmall { required: bss_size, provided: bss.len() });
+            }
+            if !bss.is_empty() && bss.as_ptr().align_offset(0x1000) != 0 {
+                free_image();
+                return Err(LoaderError::MisalignedBuffer);
+            }
+            let memory = if bss.is_empty() { std::ptr::null_mut() } else { bss.as_mut_ptr() };
+            (memory, alloc::Layout::from_size_align(bss.len(), 0x1000).unwrap(), false, bss_size)
+        },
+        None => {
+            let padded_size = bss_size + options.bss_headroom;
+            let bss_layout = alloc::Layout::from_size_align(align_up!(padded_size, options.bss_align), 0x1000).unwrap();
+            match allocate(allocator, bss_layout) {
+                Ok(memory) => (memory, bss_layout, true, padded_size),
+                Err(e) => {
+                    free_image();
+                    return Err(e);
+                }
+            }
+        }
+    };
+    let free_bss = || if owns_bss { free(allocator, bss_memory, bss_layout) };
+
+    // `module_name` leaves room for the terminator, which is already there from zeroing
+    module.Name[..module_name.len()].copy_from_slice(&module_name);
+
+    let started = options.timings.then(Instant::now);
+    let rc = ro::load_module(&mut module, image, bss_memory, loaded_size);
+    let mount_duration = started.map(|started| started.elapsed());
+
+    if rc == rc::ALREADY_LOADED {
+        free_image();
+        free_bss();
+
+        Err(LoaderError::AlreadyLoaded { name })
+    } else if rc == rc::OUT_OF_ADDRESS_SPACE {
+        free_image();
+        free_bss();
+
+        Err(LoaderError::OutOfAddressSpace { name })
+    } else if rc != 0 {
+        let mapped = std::slice::from_raw_parts(image, layout.size());
+        let retained = options.retain_failed_buffers.then(|| mapped.to_vec());
+        let relocations_total = nro::parse(mapped).ok().and_then(|view| view.relocation_count());
+
+        free_image();
+        free_bss();
+
+        Err(LoaderError::MountError { rc, image: retained, relocations_total })
+    } else {
+        let module = LoadedModule {
+            module,
+            name,
+            path,
+            hash,
+            image,
+            image_layout: layout,
+            mapped_size,
+            owns_image,
+            bss: bss_memory,
+            bss_layout,
+            owns_bss,
+            bss_size,
+            mount_duration,
+            allocator: options.allocator.clone(),
+            registration: None,
+            hooks: options.hooks.clone(),
+            settings: options.load_settings(),
+            reader: Reader::default(),
+        };
+        if options.flush_icache {
+            if let Ok(view) = nro::parse(module.mapped()) {
+                svc::flush_icache(module.base() as usize + view.text.offset, view.text.size);
+            }
+        }
+        Hooks::fire(&module.hooks.loaded, &module);
+        Ok(module)
+    }
+}
+
+/// How to handle a plugin which `nn::ro` reports as already loaded.
+///
+/// `nn::ro` offers no way to get at a module loaded by someone else, so the existing instance can't
+/// be unloaded or handed back from here. Unload it through the [`LoadedModule`] it was mounted as.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum AlreadyLoadedPolicy {
+    /// Fail the plugin with [`LoaderError::AlreadyLoaded`].
+    #[default]
+    Error,
+    /// Leave the existing instance in place and report the plugin as
+    /// [`SkipReason::AlreadyLoaded`]. This isn't treated as a failure in strict mode.
+    Skip,
+}
+
+/// Options controlling how modules are mounted.
+pub struct MountOptions {
+    retain_failed_buffers: bool,
+    strict: bool,
+    memory_budget: Option<usize>,
+    load_order: LoadOrder,
+    bootstrap: Vec<String>,
+    source: Arc<dyn PluginSource + Send + Sync>,
+    read_timeout: Option<Duration>,
+    nrr_program_id: Option<u64>,
+    nrr_kind: NrrKind,
+    directories: Vec<(PathBuf, Priority)>,
+    override_policy: OverridePolicy,
+    hooks: Hooks,
+    registration_retries: usize,
+    extra_hashes: Vec<Sha256Hash>,
+    allow_missing_dir: bool,
+    on_already_loaded: AlreadyLoadedPolicy,
+    max_hashes_per_nrr: Option<usize>,
+    sort_hashes: bool,
+    rename: Option<RenameHook>,
+    filter_meta: Option<MetaFilter>,
+    transform: Option<TransformHook>,
+    decompressor: Option<Decompressor>,
+    verify_on_mount: bool,
+    bss_align: usize,
+    bss_headroom: usize,
+    timings: bool,
+    unregister_failed: bool,
+    index_file: Option<PathBuf>,
+    include_hidden: bool,
+    registry: Option<Arc<Registry>>,
+    flush_icache: bool,
+    shed: Option<ShedHook>,
+    catch_panics: bool,
+    name_policy: NamePolicy,
+    allocator: Arc<dyn Allocator + Send + Sync>,
+}
+
+impl Default for MountOptions {
+    fn default() -> Self {
+        Self {
+            retain_failed_buffers: false,
+            strict: false,
+            memory_budget: None,
+            load_order: LoadOrder::default(),
+            bootstrap: Vec::new(),
+            source: Arc::new(StdFs),
+            read_timeout: None,
+            nrr_program_id: None,
+            nrr_kind: NrrKind::User,
+            directories: Vec::new(),
+            override_policy: OverridePolicy::default(),
+            hooks: Hooks::default(),
+            registration_retries: 0,
+            extra_hashes: Vec::new(),
+            allow_missing_dir: false,
+            on_already_loaded: AlreadyLoadedPolicy::default(),
+            max_hashes_per_nrr: None,
+            sort_hashes: true,
+            rename: None,
+            filter_meta: None,
+            transform: None,
+            decompressor: None,
+            verify_on_mount: false,
+            bss_align: 1,
+            bss_headroom: 0,
+            timings: false,
+            unregister_failed: false,
+            index_file: None,
+            include_hidden: false,
+            registry: None,
+            flush_icache: false,
+            shed: None,
+            catch_panics: false,
+            name_policy: NamePolicy::default(),
+            allocator: Arc::new(Heap),
+        }
+    }
+}
+
+impl MountOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn load_settings(&self) -> LoadSettings {
+        LoadSettings {
+            retain_failed_buffers: self.retain_failed_buffers,
+            verify_on_mount: self.verify_on_mount,
+            bss_align: self.bss_align,
+            bss_headroom: self.bss_headroom,
+            timings: self.timings,
+            flush_icache: self.flush_icache,
+            name_policy: self.name_policy,
+            nrr_kind: self.nrr_kind,
+        }
+    }
+
+    fn reader(&self) -> Reader {
+        Reader {
+            source: self.source.clone(),
+            timeout: self.read_timeout,
+            transform: self.transform.clone(),
+            decompress: self.decompressor.clone(),
+        }
+    }
+
+    /// When `LoadModule` fails, copy the image that was rejected into the returned
+    /// [`LoaderError::MountError`] so it can be inspected. Off by default.
+    pub fn retain_failed_buffers(mut self, retain: bool) -> Self {
+        self.retain_failed_buffers = retain;
+        self
+    }
+
+    /// Treat every per-plugin failure as fatal. The first plugin which fails to load aborts the
+    /// whole mount with [`LoaderError::StrictModeViolation`], after unloading anything that was
+    /// already mounted and unregistering the NRR.
+    ///
+    /// These are still only reported, and don't fail the mount:
+    /// - [`SkipReason::Hidden`]
+    /// - [`SkipReason::AlreadyRegistered`]
+    /// - [`SkipReason::AlreadyLoaded`], with [`AlreadyLoadedPolicy::Skip`]
+    /// - [`Diagnostic::BssPadded`]
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Caps the memory (page-aligned image + BSS) used by mounted plugins. Plugins are counted in
+    /// load order, and once one would exceed the budget it and every plugin after it are skipped
+    /// and reported as [`SkipReason::OverBudget`].
+    ///
+    /// Where [`available_code_space`] can be queried, the budget is also capped to it, so plugins
+    /// which wouldn't fit in the address space are skipped instead of failing mid-batch.
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    pub fn load_order(mut self, order: LoadOrder) -> Self {
+        self.load_order = order;
+        self
+    }
+
+    /// Plugins to mount before everything else, in the given sequence. Names are matched against
+    /// the file name with or without its extension, and the rest of the plugins follow in
+    /// [`LoadOrder`]. Names that don't match anything are reported as
+    /// [`Diagnostic::UnmatchedBootstrap`].
+    pub fn bootstrap(mut self, names: &[&str]) -> Self {
+        self.bootstrap = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Lists and reads plugins through `source` instead of `std::fs`, e.g. [`NnFs`] to load plugins
+    /// from the title's romfs.
+    pub fn source<S: PluginSource + Send + Sync + 'static>(mut self, source: S) -> Self {
+        self.source = Arc::new(source);
+        self
+    }
+
+    /// Gives up on a plugin whose read takes longer than `timeout`, reporting it as
+    /// [`LoaderError::ReadTimeout`]. This is only honoured by sources that can cancel reads; the
+    /// default [`StdFs`] source ignores it.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Registers the NRR under `program_id` rather than the program id passed to the mount
+    /// function, which otherwise identifies both the running program and the NRR.
+    ///
+    /// This is only legitimate when the code doing the loading runs inside a different program
+    /// than the one the plugins are meant for, e.g. a forwarder or shim which loads another
+    /// title's plugins. If `nn::ro` rejects the split, the mount fails with
+    /// [`LoaderError::ProgramIdMismatch`].
+    pub fn nrr_program_id(mut self, program_id: u64) -> Self {
+        self.nrr_program_id = Some(program_id);
+        self
+    }
+
+    /// The kind of NRR to register the plugins in. Defaults to [`NrrKind::User`]. Kinds which the
+    /// running firmware doesn't accept fail with [`LoaderError::UnsupportedNrrKind`] before
+    /// anything is registered, see [`check_nrr_support`].
+    pub fn nrr_kind(mut self, kind: NrrKind) -> Self {
+        self.nrr_kind = kind;
+        self
+    }
+
+    /// Additional directories to mount plugins from, each with a priority. The directory passed to
+    /// the mount function has [`Priority::default`].
+    pub fn directories(mut self, directories: Vec<(PathBuf, Priority)>) -> Self {
+        self.directories = directories;
+        self
+    }
+
+    /// How plugins found in several directories shadow each other. Defaults to
+    /// [`OverridePolicy::ByName`].
+    pub fn override_policy(mut self, policy: OverridePolicy) -> Self {
+        self.override_policy = policy;
+        self
+    }
+
+    /// When registration fails because a hash was rejected, find the plugin responsible, drop it
+    /// and try again, up to `retries` times. Dropped plugins are reported as
+    /// [`SkipReason::RejectedByNrr`]. If the culprit can't be identified or the retries run out,
+    /// the mount fails with the original error. Disabled (0) by default, and ignored in strict
+    /// mode.
+    ///
+    /// Finding the plugin registers and unregisters probe NRRs for halves of the remaining set, so
+    /// each retry costs around `log2(plugins)` extra registrations.
+    pub fn registration_retries(mut self, retries: usize) -> Self {
+        self.registration_retries = retries;
+        self
+    }
+
+    /// Once everything is mounted, replace any NRR holding hashes of plugins which failed to mount
+    /// with one that leaves them out, so the registered set matches what's loaded. Off by default,
+    /// in which case those hashes are reported by [`MountInfo::dangling_hashes`] but stay
+    /// registered. Doesn't apply to strict mode, where a failed plugin fails the whole mount.
+    pub fn unregister_failed(mut self, unregister: bool) -> Self {
+        self.unregister_failed = unregister;
+        self
+    }
+
+    /// Adds hashes to the NRR for modules which aren't in the plugin directory yet, so they can be
+    /// mounted later (e.g. with [`mount_nro`]) while the resulting [`MountInfo`] is mounted.
+    /// NRRs can't be changed once registered, so this avoids needing a second one.
+    ///
+    /// A module mounted this way has to hash to exactly one of these, i.e. be byte-for-byte the
+    /// file which was hashed. Hashes which match a mounted plugin are only added once.
+    pub fn extra_hashes(mut self, hashes: &[Sha256Hash]) -> Self {
+        self.extra_hashes = hashes.to_vec();
+        self
+    }
+
+    /// Treat a plugin directory which doesn't exist as containing no plugins, instead of failing
+    /// with [`LoaderError::PluginDirMissing`].
+    pub fn allow_missing_dir(mut self, allow: bool) -> Self {
+        self.allow_missing_dir = allow;
+        self
+    }
+
+    /// What to do when `LoadModule` reports that a plugin is already loaded, usually because a
+    /// previous instance was never unloaded. Defaults to [`AlreadyLoadedPolicy::Error`].
+    pub fn on_already_loaded(mut self, policy: AlreadyLoadedPolicy) -> Self {
+        self.on_already_loaded = policy;
+        self
+    }
+
+    /// Decides what to unload when `LoadModule` runs out of address space partway through a
+    /// mount. `shed` is passed the modules mounted so far and returns the name of one to unload,
+    /// after which the plugin is tried again, or `None` to give up on it. Unloaded modules are
+    /// reported as [`SkipReason::Shed`]. If a module can't be unloaded, the whole mount is rolled
+    /// back and fails with the [`LoaderError::UnloadError`]. Ignored in strict mode, where running
+    /// out of address space fails the mount like any other plugin failure.
+    ///
+    /// Without this, the plugin fails with [`LoaderError::OutOfAddressSpace`] and the mount
+    /// carries on with the next one. Either way, the modules which did get mounted are kept.
+    pub fn on_out_of_address_space<F>(mut self, shed: F) -> Self
+    where
+        F: Fn(&[ModuleInfo]) -> Option<String> + Send + Sync + 'static
+    {
+        self.shed = Some(Arc::new(shed));
+        self
+    }
+
+    /// Splits the plugins across several NRRs with at most `count` hashes each. Defaults to
+    /// [`max_modules_per_nrr`], so everything goes in one NRR unless there are more hashes than
+    /// that. See [`MountInfo::registrations`] for how they were split.
+    pub fn max_hashes_per_nrr(mut self, count: usize) -> Self {
+        self.max_hashes_per_nrr = Some(count.max(1));
+        self
+    }
+
+    /// Whether to sort the hashes in the NRR, on by default. See [`NrrBuilder::sort`] for why
+    /// turning this off is risky. [`MountInfo::iter_nrr_order`] follows whichever order was used.
+    pub fn sort_hashes(mut self, sort: bool) -> Self {
+        self.sort_hashes = sort;
+        self
+    }
+
+    /// Gives plugins a name other than their file name. The name is used for `Module.Name`,
+    /// [`LoadedModule::name`] and [`MountOptions::bootstrap`] matching, while the path is kept for
+    /// reloading. Returning `None` keeps the file name.
+    ///
+    /// Names have to fit in `Module.Name` (255 bytes plus a terminator), longer ones are handled
+    /// according to [`MountOptions::name_policy`].
+    pub fn rename<F: Fn(&Path) -> Option<String> + Send + Sync + 'static>(mut self, rename: F) -> Self {
+        self.rename = Some(Arc::new(rename));
+        self
+    }
+
+    /// Skips plugins based on their embedded [`PluginMeta`], e.g. to only load plugins built for
+    /// the running game version. Plugins without metadata are passed `None`, so it's up to the
+    /// filter whether to keep them. Skipped plugins are reported as [`SkipReason::FilteredByMeta`].
+    pub fn filter_meta<F: Fn(Option<&PluginMeta>) -> bool + Send + Sync + 'static>(mut self, filter: F) -> Self {
+        self.filter_meta = Some(Arc::new(filter));
+        self
+    }
+
+    /// Rewrites each plugin's bytes as soon as they've been read, e.g. to decrypt or patch them.
+    /// Everything after (validation, the BSS fix-up, hashing and mounting) sees the transformed
+    /// bytes, so the hash in the NRR is of those. Output which isn't a valid NRO fails the plugin
+    /// with [`LoaderError::InvalidNro`]. Reloading a module runs the transform again.
+    pub fn transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(&Path, Vec<u8>) -> Result<Vec<u8>, LoaderError> + Send + Sync + 'static
+    {
+        self.transform = Some(Arc::new(transform));
+        self
+    }
+
+    /// Inflates packed plugins. Files which don't start with an NRO header are passed to
+    /// `decompressor` (after [`MountOptions::transform`], if set), and the output is used in their
+    /// place. Plain NROs are left alone, so packed and unpacked plugins can be mixed.
+    ///
+    /// Hashing happens after decompression, so the NRR matches the one a plain NRO would get. No
+    /// codec is built in; wrap whichever compression library the plugins were packed with.
+    pub fn decompressor<F>(mut self, decompressor: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<Vec<u8>, LoaderError> + Send + Sync + 'static
+    {
+        self.decompressor = Some(Arc::new(decompressor));
+        self
+    }
+
+    /// Hash each module again right before `LoadModule`, over the exact buffer passed to it, and
+    /// fail with [`LoaderError::HashMismatch`] if it no longer matches the hash in the NRR. This
+    /// catches the buffer changing after it was hashed, e.g. a mapped buffer still being written.
+    /// Off by default, as it hashes every module twice.
+    pub fn verify_on_mount(mut self, verify: bool) -> Self {
+        self.verify_on_mount = verify;
+        self
+    }
+
+    /// Allocates module images and BSS buffers from `allocator` instead of the heap, e.g. an
+    /// [`Arena`]. Modules keep a reference to it, and reloading a module allocates from it again.
+    /// NRRs are always allocated on the heap.
+    pub fn allocator<A: Allocator + Send + Sync + 'static>(mut self, allocator: Arc<A>) -> Self {
+        self.allocator = allocator;
+        self
+    }
+
+    /// Pads each module's BSS allocation up to a multiple of `align`, which can help with
+    /// corruption next to the BSS seen on some firmware. `nn::ro` is still told the size
+    /// `GetBufferSize` asked for, plus any [`MountOptions::bss_headroom`]. By default the
+    /// allocation is exactly that size.
+    ///
+    /// # Panics
+    /// If `align` isn't a power of two.
+    pub fn bss_align(mut self, align: usize) -> Self {
+        assert!(align.is_power_of_two(), "BSS alignment must be a power of two");
+        self.bss_align = align;
+        self
+    }
+
+    /// Allocates `bytes` more BSS than `GetBufferSize` asks for and tells `LoadModule` about the
+    /// extra room, for firmware where modules have been seen to use more BSS than reported. Each
+    /// padded module is reported as [`Diagnostic::BssPadded`]. Doesn't apply to BSS buffers passed
+    /// to [`mount_with_bss`]. Defaults to 0.
+    pub fn bss_headroom(mut self, bytes: usize) -> Self {
+        self.bss_headroom = bytes;
+        self
+    }
+
+    /// Time each module's `LoadModule` call, see [`LoadedModule::mount_duration`]. Off by default,
+    /// in which case no clock is read.
+    pub fn timings(mut self, timings: bool) -> Self {
+        self.timings = timings;
+        self
+    }
+
+    /// Only mount the plugins listed in `path`, a text file with one path per line, in the order
+    /// they're listed. `path` and every entry are relative to the plugin directory, and blank lines
+    /// are ignored. Everything else in the directory (and in [`MountOptions::directories`]) is left
+    /// alone, and the validator isn't called, as the index already decides what gets loaded.
+    ///
+    /// Entries which can't be read are reported as failed modules in [`MountInfo::modules`] like
+    /// any other unreadable plugin. [`LoadOrder::Discovery`] keeps the order of the index.
+    pub fn index_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.index_file = Some(path.into());
+        self
+    }
+
+    /// Pass files whose name starts with a `.` (e.g. `.DS_Store` or macOS `._plugin.nro` resource
+    /// forks) to the validator like any other file. By default they're left out before the
+    /// validator runs and reported as [`SkipReason::Hidden`].
+    pub fn include_hidden(mut self, include: bool) -> Self {
+        self.include_hidden = include;
+        self
+    }
+
+    /// Shares registered hashes with other mounts using the same `registry`, so that mounting
+    /// overlapping sets of plugins only registers and mounts each plugin once. Plugins whose hash
+    /// an earlier mount already registered are skipped as [`SkipReason::AlreadyRegistered`], and
+    /// [`MountOptions::extra_hashes`] already in the registry aren't added again. Without a
+    /// registry, every mount registers all of its plugins.
+    pub fn registry(mut self, registry: Arc<Registry>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Flush the instruction cache over each module's text segment after mounting it, before the
+    /// [`MountOptions::on_module_loaded`] hook runs. `nn::ro` maps code through the kernel, which
+    /// handles this on retail Horizon, so it's off by default. It's meant for emulators and
+    /// custom kernels which don't, where skipping it shows up as rare crashes on a module's first
+    /// call. Does nothing on targets other than the Switch.
+    pub fn flush_icache(mut self, flush: bool) -> Self {
+        self.flush_icache = flush;
+        self
+    }
+
+    /// Catch panics while reading and parsing each plugin (including in
+    /// [`MountOptions::transform`] and [`MountOptions::decompressor`]), so a file which triggers
+    /// one fails with [`LoaderError::Panicked`] instead of taking the whole mount down. This only
+    /// helps in builds with `panic = "unwind"`; with `panic = "abort"` the process still aborts.
+    pub fn catch_panics(mut self, catch: bool) -> Self {
+        self.catch_panics = catch;
+        self
+    }
+
+    /// How to handle names which are too long for `Module.Name` (255 bytes plus a terminator),
+    /// [`NamePolicy::Reject`] by default. Only `Module.Name` is affected: [`LoadedModule::name`]
+    /// is always the full name.
+    pub fn name_policy(mut self, policy: NamePolicy) -> Self {
+        self.name_policy = policy;
+        self
+    }
+
+    /// Called with each plugin's path and hash as soon as it's been hashed, before anything is
+    /// mounted, e.g. to write out an allowlist while scanning. Plugins which can't be read or
+    /// parsed aren't hashed, so they don't trigger this.
+    pub fn on_hash<F: FnMut(&Path, &Sha256Hash) + Send + 'static>(mut self, hook: F) -> Self {
+        self.hooks.hashed = Some(Arc::new(Mutex::new(hook)));
+        self
+    }
+
+    /// Called right after each module is mounted.
+    pub fn on_module_loaded<F: FnMut(&ModuleInfo) + Send + 'static>(mut self, hook: F) -> Self {
+        self.hooks.loaded = Some(Arc::new(Mutex::new(hook)));
+        self
+    }
+
+    /// Called just before each module is unloaded, whether by [`LoadedModule::unload`],
+    /// [`LoadedModule::reload`], [`MountInfo::unload_all`] or a strict mode rollback. Modules are
+    /// never unloaded on drop, so a module which is dropped without being unloaded doesn't trigger
+    /// this.
+    pub fn on_module_unloaded<F: FnMut(&ModuleInfo) + Send + 'static>(mut self, hook: F) -> Self {
+        self.hooks.unloaded = Some(Arc::new(Mutex::new(hook)));
+        self
+    }
+}
+
+/// A module's name along with the result of mounting it, as returned by [`MountInfo::into_parts`].
+pub type NamedModule = (String, Result<LoadedModule, LoaderError>);
+
+/// A summary of one of the NRRs in a [`MountInfo`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RegistrationView {
+    pub program_id: u64,
+    /// The number of hashes in the NRR, including any [`MountOptions::extra_hashes`].
+    pub module_count: usize,
+    pub image_size: usize,
+}
+
+pub struct MountInfo {
+    /// The program id passed to the mount function.
+    pub program_id: u64,
+    pub modules: Vec<Result<LoadedModule, LoaderError>>,
+    // The name of each entry in `modules`, so failed modules still have one in `into_parts`
+    names: Vec<String>,
+    // Usually a single NRR, unless there were more hashes than fit in one (see
+    // `MountOptions::max_hashes_per_nrr`)
+    registrations: Vec<Registration>,
+    // From `MountOptions::extra_hashes`, which aren't dangling despite having no module
+    extra_hashes: Vec<Sha256Hash>,
+    /// Plugins which were skipped because an identical file had already been found, as
+    /// `(dropped, kept)` pairs.
+    pub deduped: Vec<(PathBuf, PathBuf)>,
+    /// Plugins which were shadowed by a plugin from a higher priority directory, as
+    /// `(overridden, winner)` pairs. See [`MountOptions::override_policy`].
+    pub overridden: Vec<(PathBuf, PathBuf)>,
+    pub diagnostics: Vec<Diagnostic>,
+    preferred_order: Option<Vec<String>>,
+    pinned: HashSet<String>,
+}
+
+impl MountInfo {
+    /// Records a new order for the modules, for the host to persist and pass back as
+    /// [`LoadOrder::Explicit`] next time. Nothing is remounted. Every name has to belong to a
+    /// mounted module, otherwise this fails with [`LoaderError::UnknownModule`] and the previous
+    /// order is kept.
+    pub fn set_preferred_order(&mut self, names: &[&str]) -> Result<(), LoaderError> {
+        if let Some(name) = names.iter().find(|name| !self.iter_load_order().any(|module| module.name == **name)) {
+            return Err(LoaderError::UnknownModule { name: name.to_string() });
+        }
+        self.preferred_order = Some(names.iter().map(|name| name.to_string()).collect());
+        Ok(())
+    }
+
+    /// The order set with [`MountInfo::set_preferred_order`], or the current load order if none
+    /// was set.
+    pub fn preferred_order(&self) -> LoadOrder {
+        LoadOrder::Explicit(self.preferred_order.clone().unwrap_or_else(|| {
+            self.iter_load_order().map(|module| module.name.clone()).collect()
+        }))
+    }
+
+    /// Iterates the successfully mounted modules in the order they were mounted.
+    pub fn iter_load_order(&self) -> impl Iterator<Item = &LoadedModule> {
+        self.modules.iter().filter_map(|module| module.as_ref().ok())
+    }
+
+    /// Iterates the successfully mounted modules in the order their hashes appear in the NRR,
+    /// which is the order `nn::ro` sees them in.
+    pub fn iter_nrr_order(&self) -> impl Iterator<Item = &LoadedModule> {
+        let positions: HashMap<Sha256Hash, usize> = self.registrations.iter()
+            .flat_map(Registration::hashes)
+            .enumerate()
+            .map(|(index, hash)| (*hash, index))
+            .collect();
+
+        let mut modules: Vec<&LoadedModule> = self.iter_load_order().collect();
+        modules.sort_by_key(|module| positions.get(&module.hash).copied());
+        modules.into_iter()
+    }
+
+    /// Where `hash` was written, as the index of the NRR in [`MountInfo::registrations`] and the
+    /// slot in that NRR's hash table. With [`MountOptions::sort_hashes`] off, slots follow the load
+    /// order.
+    pub fn nrr_position(&self, hash: &Sha256Hash) -> Option<(usize, usize)> {
+        self.registrations.iter().enumerate().find_map(|(index, registration)| {
+            registration.hashes.iter().position(|registered| registered == hash).map(|slot| (index, slot))
+        })
+    }
+
+    /// The successfully mounted modules, largest [`LoadedModule::footprint`] first. Modules of the
+    /// same size are sorted by name.
+    pub fn modules_by_size(&self) -> Vec<&LoadedModule> {
+        let mut modules: Vec<&LoadedModule> = self.iter_load_order().collect();
+        modules.sort_by(|a, b| b.footprint().cmp(&a.footprint()).then_with(|| a.name.cmp(&b.name)));
+        modules
+    }
+
+    /// The successfully mounted modules, slowest [`LoadedModule::mount_duration`] first. Modules
+    /// without a duration come last, and ties are sorted by name.
+    pub fn modules_by_mount_time(&self) -> Vec<&LoadedModule> {
+        let mut modules: Vec<&LoadedModule> = self.iter_load_order().collect();
+        modules.sort_by(|a, b| b.mount_duration.cmp(&a.mount_duration).then_with(|| a.name.cmp(&b.name)));
+        modules
+    }
+
+    /// Computes a single hash identifying the set of successfully mounted modules. The module
+    /// hashes are sorted (matching their order in the NRR) before being hashed, so the result
+    /// doesn't depend on the order plugins were discovered in.
+    pub fn set_fingerprint(&self) -> Sha256Hash {
+        let mut hashes: Vec<Sha256Hash> = self.modules.iter()
+            .filter_map(|module| module.as_ref().ok())
+            .map(LoadedModule::hash)
+            .collect();
+        hashes.sort();
+
+        let bytes: Vec<u8> = hashes.iter().flat_map(|hash| hash.0).collect();
+        Sha256Hash::new(&bytes)
+    }
+
+    /// Every hash currently registered for these modules, sorted and without duplicates. This is
+    /// what was actually registered, so hashes dropped by [`MountOptions::registration_retries`]
+    /// are left out, while [`MountOptions::extra_hashes`] and the NRRs of reloaded modules are
+    /// included.
+    pub fn registered_hashes(&self) -> Vec<Sha256Hash> {
+        let mut hashes: Vec<Sha256Hash> = self.registrations.iter()
+            .chain(self.iter_load_order().filter_map(|module| module.registration.as_ref()))
+            .flat_map(|registration| registration.hashes.iter().copied())
+            .collect();
+        hashes.sort();
+        hashes.dedup();
+        hashes
+    }
+
+    /// Hashes which are registered but don't belong to a mounted module, sorted. These are left
+    /// behind by plugins which failed to mount or were unloaded, and stay registered (and count
+    /// against `nn::ro`'s limits) until [`MountInfo::unload_all`]. Hashes from
+    /// [`MountOptions::extra_hashes`] aren't included. See [`MountOptions::unregister_failed`].
+    pub fn dangling_hashes(&self) -> Vec<Sha256Hash> {
+        dangling_hashes(&self.registrations, &self.modules, &self.extra_hashes)
+    }
+
+    /// The NRRs the plugins were registered in, in registration order.
+    pub fn registrations(&self) -> impl Iterator<Item = RegistrationView> + '_ {
+        self.registrations.iter().map(|registration| RegistrationView {
+            program_id: registration.program_id,
+            module_count: registration.hashes.len(),
+            image_size: registration.image_size(),
+        })
+    }
+
+    /// The number of NRRs the plugins were registered in.
+    pub fn nrr_count(&self) -> usize {
+        self.registrations.len()
+    }
+
+    /// Whether the hashes did not fit in a single NRR and were split across several, which means
+    /// the deployment is getting close to [`max_modules_per_nrr`].
+    pub fn chunked(&self) -> bool {
+        self.nrr_count() > 1
+    }
+
+    /// Takes the modules and NRRs out, for callers which manage their lifetimes themselves. Each
+    /// module comes with its name, which for modules that failed is their file name.
+    ///
+    /// From here on the caller is responsible for unloading every module with
+    /// [`LoadedModule::unload`] and only then unregistering every NRR with
+    /// [`Registration::unregister`]. Modules aren't unloaded on drop, and an NRR which is dropped
+    /// without being unregistered stays registered with its image leaked, so dropping the parts
+    /// leaves everything loaded.
+    pub fn into_parts(self) -> (Vec<NamedModule>, Vec<Registration>) {
+        // `modules` is public and may have been changed since, so don't rely on the lengths matching
+        let names = self.names.into_iter().chain(std::iter::repeat_with(String::new));
+        let modules = names.zip(self.modules)
+            .map(|(name, module)| match &module {
+                Ok(loaded) => (loaded.name.clone(), module),
+                Err(_) => (name, module),
+            })
+            .collect();
+        (modules, self.registrations)
+    }
+
+    /// How many more modules the process could load, assuming the modules mounted here are the only
+    /// ones loaded. See [`max_loaded_modules`].
+    pub fn remaining_capacity(&self) -> usize {
+        max_loaded_modules().saturating_sub(self.iter_load_order().count())
+    }
+
+    /// Protects the module called `name` from [`MountInfo::unload_all`] and
+    /// [`PluginManager::reload_changed`], e.g. a core plugin which installs hooks the others rely
+    /// on. Fails with [`LoaderError::UnknownModule`] if no mounted module has that name.
+    pub fn pin(&mut self, name: &str) -> Result<(), LoaderError> {
+        if !self.iter_load_order().any(|module| module.name == name) {
+            return Err(LoaderError::UnknownModule { name: name.to_owned() });
+        }
+        self.pinned.insert(name.to_owned());
+        Ok(())
+    }
+
+    /// Undoes [`MountInfo::pin`]. Returns whether the module was pinned.
+    pub fn unpin(&mut self, name: &str) -> bool {
+        self.pinned.remove(name)
+    }
+
+    pub fn is_pinned(&self, name: &str) -> bool {
+        self.pinned.contains(name)
+    }
+
+    /// Unloads every mounted module in reverse load order, then unregisters the NRRs.
+    ///
+    /// Stops at the first module which fails to unload. The modules after it in reverse order stay
+    /// loaded and the NRRs stay registered, since they still depend on them.
+    ///
+    /// Pinned modules are skipped and stay loaded for good, along with the NRRs holding their
+    /// hashes. They're leaked on purpose, as nothing can unload them once this returns.
+    pub fn unload_all(self) -> Result<(), LoaderError> {
+        self.unload_unpinned().map(drop)
+    }
+
+    /// Like [`MountInfo::unload_all`], but hands back the pinned modules, in load order, and the
+    /// NRRs holding their hashes instead of leaking them.
+    fn unload_unpinned(self) -> Result<(Vec<LoadedModule>, Vec<Registration>), LoaderError> {
+        let mut pinned = Vec::new();
+        for module in self.modules.into_iter().flatten().rev() {
+            if self.pinned.contains(&module.name) {
+                pinned.push(module);
+                continue;
+            }
+            module.unload()?;
+        }
+        pinned.reverse();
+
+        let mut kept = Vec::new();
+        for registration in self.registrations {
+            if registration.hashes.iter().any(|hash| pinned.iter().any(|module| module.hash == *hash)) {
+                kept.push(registration);
+                continue;
+            }
+            registration.unregister()?;
+        }
+        Ok((pinned, kept))
+    }
+}
+
+/// Checks that the running firmware accepts NRRs of `kind`, see [`NrrKind::min_firmware`], so an
+/// old firmware fails with [`LoaderError::UnsupportedNrrKind`] rather than an opaque registration
+/// error. If the firmware version can't be read, nothing is checked and this succeeds.
+///
+/// Every NRR is checked this way before it's registered.
+pub fn check_nrr_support(kind: NrrKind) -> Result<(), LoaderError> {
+    let required = kind.min_firmware();
+    if required == (0, 0, 0) {
+        return Ok(());
+    }
+
+    let Some(running) = ro::firmware_version() else { return Ok(()) };
+    if running < required {
+        return Err(LoaderError::UnsupportedNrrKind { kind, required, running });
+    }
+    Ok(())
+}
+
+/// Builds an NRR containing `hashes` and registers it with `nn::ro` under `program_id`.
+///
+/// An NRO can only be mounted (e.g. with [`mount_nro`]) while an NRR containing its hash is
+/// registered, so this must be called before mounting the corresponding modules. The hashes are
+/// sorted before being written, as `nn::ro` expects.
+pub fn register_nrr(program_id: u64, hashes: &[Sha256Hash]) -> Result<Registration, LoaderError> {
+    register_built(&NrrBuilder::new(program_id).hashes(hashes))
+}
+
+fn register_built(builder: &NrrBuilder) -> Result<Registration, LoaderError> {
+    use std::alloc;
+
+    check_nrr_support(builder.nrr_kind())?;
+
+    let layout = alloc::Layout::from_size_align(builder.image_size(), 0x1000).unwrap();
+    unsafe {
+        let memory = allocate(&Heap, layout)?;
+        builder.write(std::slice::from_raw_parts_mut(memory, layout.size()));
+
+        let mut nrr_info = std::mem::MaybeUninit::uninit();
+        let rc = ro::register_module_info(nrr_info.as_mut_ptr(), memory);
+        if rc != 0 {
+            free(&Heap, memory, layout);
+            return Err(LoaderError::RegistrationError(rc));
+        }
+        Ok(Registration {
+            info: nrr_info.assume_init(),
+            image: memory,
+            layout,
+            program_id: builder.program_id(),
+            hashes: builder.ordered_hashes(),
+            registry: None,
+        })
+    }
+}
+
+/// Checks that `bytes` is a well-formed NRO which could be mounted, without hashing or mounting
+/// it, see [`nro::validate`]. Useful for vetting bytes from an untrusted source before passing
+/// them on.
+pub fn is_valid_nro(bytes: &[u8]) -> Result<(), LoaderError> {
+    nro::validate(bytes)
+}
+
+/// The null-terminated form of a symbol name, which `nn::ro` can only be asked about if it's
+/// non-empty and has no NUL of its own.
+fn symbol_name(name: &str) -> Result<CString, LoaderError> {
+    let invalid = || LoaderError::InvalidSymbolName { name: name.to_owned() };
+    if name.is_empty() {
+        return Err(invalid());
+    }
+    CString::new(name).map_err(|_| invalid())
+}
+
+/// What `nn::ro::LookupModuleSymbol` returning `rc` and `address` for `name` means.
+fn lookup_result(name: &str, rc: u32, address: usize) -> Result<*const (), LoaderError> {
+    match (rc, address) {
+        (0, 0) => Err(LoaderError::SymbolNotFound { name: name.to_owned() }),
+        (0, address) => Ok(address as *const ()),
+        (rc, _) => Err(LoaderError::LookupError { name: name.to_owned(), rc }),
+    }
+}
+
+/// Looks up every name in `names` with `lookup`, stopping at the first which fails.
+fn resolve_with<F: FnMut(&str) -> Result<*const (), LoaderError>>(names: &[&str], lookup: F) -> Result<Vec<*const ()>, LoaderError> {
+    names.iter().copied().map(lookup).collect()
+}
+
+/// Mounts a single NRO image.
+///
+/// The bytes are mounted exactly as given, so `Sha256Hash::new(bytes)` must be part of an NRR
+/// which is currently registered (see [`register_nrr`]), otherwise `nn::ro` rejects the module.
+/// The registration has to outlive the module.
+pub fn mount_nro(bytes: &[u8], name: &str, options: &MountOptions) -> Result<LoadedModule, LoaderError> {
+    check_name(name, options.name_policy)?;
+    nro::parse(bytes)?;
+    let nro = NroFile { data: bytes.to_vec(), name: name.to_string(), path: None };
+    let hash = nro.hash();
+    nro.mount(hash, options)
+}
+
+/// Mounts a single NRO image like [`mount_nro`], but with a BSS buffer from the caller instead of
+/// one allocated here.
+///
+/// `bss` has to be at least as large as `nn::ro::GetBufferSize` asks for, otherwise this fails
+/// with [`LoaderError::BssTooSmall`], and aligned to 0x1000, otherwise it fails with
+/// [`LoaderError::MisalignedBuffer`]. The module never frees it.
+///
+/// # Safety
+/// `nn::ro` takes `bss` over while the module is loaded, so the caller must not read, write or free
+/// it until [`LoadedModule::unload`] has returned.
+pub unsafe fn mount_with_bss(bytes: &[u8], name: &str, bss: &mut [u8], options: &MountOptions) -> Result<LoadedModule, LoaderError> {
+    check_name(name, options.name_policy)?;
+    nro::parse(bytes)?;
+    let nro = NroFile { data: bytes.to_vec(), name: name.to_string(), path: None };
+    let hash = nro.hash();
+    nro.mount_with_bss(hash, options, Some(bss))
+}
+
+/// Registers and mounts an NRO which is already in memory, without copying it.
+///
+/// The mapping is hashed and registered in a new NRR under `program_id` (or
+/// [`MountOptions::nrr_program_id`]), which the returned module owns and unregisters when it is
+/// unloaded. The BSS is allocated as usual.
+///
+/// # Safety
+/// - `ptr` must point to `len` bytes holding an NRO image, aligned to 0x1000 and padded to a
+///   multiple of 0x1000, as required by `LoadModule`.
+/// - `nn::ro` takes the buffer over while the module is loaded, so the caller must not read, write
+///   or free it until [`LoadedModule::unload`] has returned. The loader never frees it; that's the
+///   caller's job once the module is unloaded, or right away if this returns an error.
+pub unsafe fn mount_from_mapped(
+    program_id: u64,
+    ptr: *mut u8,
+    len: usize,
+    name: &str,
+    options: &MountOptions
+) -> Result<LoadedModule, LoaderError> {
+    if ptr.align_offset(0x1000) != 0 || align_up!(len, 0x1000) != len {
+        return Err(LoaderError::MisalignedBuffer);
+    }
+    let layout = Layout::from_size_align(len, 0x1000).unwrap();
+
+    check_name(name, options.name_policy)?;
+    let bytes = std::slice::from_raw_parts(ptr, len);
+    nro::parse(bytes)?;
+    let hash = Sha256Hash::new(bytes);
+
+    let builder = NrrBuilder::new(options.nrr_program_id.unwrap_or(program_id)).kind(options.nrr_kind);
+    let registration = register_built(&builder.hashes(&[hash]))?;
+    match load_image(ptr, layout, false, None, name.to_string(), None, hash, options) {
+        Ok(mut module) => {
+            module.registration = Some(registration);
+            Ok(module)
+        },
+        Err(e) => {
+            let _ = registration.unregister();
+            Err(e)
+        }
+    }
+}
+
+pub fn mount_from_directory<P: AsRef<Path>, F: Fn(&Path) -> bool>(program_id: u64, path: P, validator: F) -> Result<MountInfo, LoaderError> {
+    mount_from_directory_with_options(program_id, path, validator, &MountOptions::default())
+}
+
+pub fn mount_from_directory_with_options<P: AsRef<Path>, F: Fn(&Path) -> bool>(
+    program_id: u64,
+    path: P,
+    validator: F,
+    options: &MountOptions
+) -> Result<MountInfo, LoaderError> {
+    let Discovered { plugins, hidden, duplicates } = discover(options, path.as_ref(), validator)?;
+    mount_plugins(program_id, prepare_all(plugins, options), hidden, duplicates, options)
+}
+
+/// Reads, validates and hashes every discovered plugin, calling [`MountOptions::on_hash`] as it
+/// goes.
+fn prepare_all(plugins: Vec<(PathBuf, Priority)>, options: &MountOptions) -> Vec<PreparedPlugin> {
+    let reader = options.reader();
+    plugins.into_iter()
+        .map(|(path, priority)| {
+            let nro = prepare_isolated(&reader, &path, options.catch_panics);
+            let plugin = PreparedPlugin { path, priority, nro };
+            options.hooks.fire_hashed(&plugin);
+            plugin
+        })
+        .collect()
+}
+
+/// Prepares a single plugin, turning a panic into [`LoaderError::Panicked`] if `catch_panics` is
+/// set (see [`MountOptions::catch_panics`]).
+fn prepare_isolated(reader: &Reader, path: &Path, catch_panics: bool) -> Result<(NroFile, Sha256Hash), LoaderError> {
+    if !catch_panics {
+        return NroFile::prepare(reader, path);
+    }
+    // Preparing only works on owned buffers, which unwinding frees, and the reader is only shared
+    // through `Arc`s, so nothing is left half-updated
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| NroFile::prepare(reader, path)))
+        .unwrap_or_else(|_| Err(LoaderError::Panicked { path: path.to_path_buf() }))
+}
+
+struct Discovered {
+    plugins: Vec<(PathBuf, Priority)>,
+    // Left out because of `MountOptions::include_hidden`, before the validator saw them
+    hidden: Vec<PathBuf>,
+    // The same file as an earlier plugin, see `SkipReason::SymlinkCycle`
+    duplicates: Vec<PathBuf>,
+}
+
+fn is_hidden(path: &Path) -> bool {
+    matches!(path.file_name(), Some(name) if name.to_string_lossy().starts_with('.'))
+}
+
+/// Lists the plugins in `path` and every directory from [`MountOptions::directories`], along with
+/// the priority of the directory each was found in. `path` itself has the default priority.
+fn discover<F: Fn(&Path) -> bool>(
+    options: &MountOptions,
+    path: &Path,
+    validator: F
+) -> Result<Discovered, LoaderError> {
+    if let Some(index) = &options.index_file {
+        let (plugins, duplicates) = dedup_real_paths(read_index(options, path, index)?);
+        return Ok(Discovered { plugins, hidden: Vec::new(), duplicates });
+    }
+
+    let directories = std::iter::once((path, Priority::default()))
+        .chain(options.directories.iter().map(|(path, priority)| (path.as_path(), *priority)));
+
+    let mut plugins = Vec::new();
+    let mut hidden = Vec::new();
+    for (directory, priority) in directories {
+        let mut paths = match options.source.list(directory) {
+            Ok(paths) => paths,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if options.allow_missing_dir {
+                    continue;
+                }
+                return Err(LoaderError::PluginDirMissing { path: directory.to_path_buf() });
+            },
+            Err(e) => return Err(e.into()),
+        };
+        if !options.include_hidden {
+            let (dotfiles, rest): (Vec<_>, Vec<_>) = paths.into_iter().partition(|path| is_hidden(path));
+            hidden.extend(dotfiles);
+            paths = rest;
+        }
+        paths.retain(|path| validator(path));
+        plugins.extend(paths.into_iter().map(|path| (path, priority)));
+    }
+    let (plugins, duplicates) = dedup_real_paths(plugins);
+    Ok(Discovered { plugins, hidden, duplicates })
+}
+
+/// Splits off plugins whose canonical path was already seen, keeping the first one. Paths which
+/// can't be canonicalized (e.g. ones from a custom [`PluginSource`]) are compared as they are.
+fn dedup_real_paths(plugins: Vec<(PathBuf, Priority)>) -> (Vec<(PathBuf, Priority)>, Vec<PathBuf>) {
+    let mut seen = HashSet::new();
+    let (plugins, duplicates): (Vec<_>, Vec<_>) = plugins.into_iter()
+        .partition(|(path, _)| seen.insert(std::fs::canonicalize(path).unwrap_or_else(|_| path.clone())));
+    (plugins, duplicates.into_iter().map(|(path, _)| path).collect())
+}
+
+/// Reads the plugins listed in the index file at `index`, resolving everything relative to `root`.
+fn read_index(options: &MountOptions, root: &Path, index: &Path) -> Result<Vec<(PathBuf, Priority)>, LoaderError> {
+    let index = options.source.read(&root.join(index), options.read_timeout)?;
+    Ok(String::from_utf8_lossy(&index)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| (root.join(line), Priority::default()))
+        .collect())
+}
+
+struct PreparedPlugin {
+    path: PathBuf,
+    priority: Priority,
+    nro: Result<(NroFile, Sha256Hash), LoaderError>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum OverrideKey {
+    Name(std::ffi::OsString),
+    Hash(Sha256Hash),
+}
+
+impl PreparedPlugin {
+    /// Whether `name` refers to this plugin, either by file name or by the name it was given by
+    /// [`MountOptions::rename`].
+    fn matches(&self, name: &str) -> bool {
+        plugin_matches(&self.path, name) || matches!(&self.nro, Ok((nro, _)) if nro.name == name)
+    }
+
+    fn override_key(&self, policy: OverridePolicy) -> Option<OverrideKey> {
+        match policy {
+            OverridePolicy::ByName => self.path.file_name().map(|name| OverrideKey::Name(name.to_os_string())),
+            OverridePolicy::ByHash => self.nro.as_ref().ok().map(|(_, hash)| OverrideKey::Hash(*hash)),
+            OverridePolicy::None => None,
+        }
+    }
+}
+
+fn mount_plugins(
+    program_id: u64,
+    mut plugins: Vec<PreparedPlugin>,
+    hidden: Vec<PathBuf>,
+    duplicates: Vec<PathBuf>,
+    options: &MountOptions
+) -> Result<MountInfo, LoaderError> {
+    let mut diagnostics: Vec<Diagnostic> = duplicates.into_iter()
+        .map(|path| Diagnostic::Skipped { path, reason: SkipReason::SymlinkCycle })
+        .collect();
+    // Skips which are expected and so don't trip strict mode, added to the rest at the end
+    let mut quiet: Vec<Diagnostic> = hidden.into_iter()
+        .map(|path| Diagnostic::Skipped { path, reason: SkipReason::Hidden })
+        .collect();
+
+    if let Some(rename) = &options.rename {
+        for plugin in plugins.iter_mut() {
+            if let (Ok((nro, _)), Some(name)) = (&mut plugin.nro, rename(&plugin.path)) {
+                nro.name = name;
+            }
+        }
+    }
+
+    match &options.load_order {
+        LoadOrder::Discovery => {},
+        LoadOrder::FileName => plugins.sort_by(|a, b| a.path.file_name().cmp(&b.path.file_name())),
+        // Stable, so unlisted plugins keep their discovery order
+        LoadOrder::Explicit(names) => plugins.sort_by_key(|plugin| {
+            names.iter().position(|name| plugin.matches(name)).unwrap_or(names.len())
+        }),
+    }
+
+    let mut bootstrapped = Vec::with_capacity(options.bootstrap.len());
+    for name in options.bootstrap.iter() {
+        match plugins.iter().position(|plugin| plugin.matches(name)) {
+            Some(index) => bootstrapped.push(plugins.remove(index)),
+            None => diagnostics.push(Diagnostic::UnmatchedBootstrap { name: name.clone() }),
+        }
+    }
+    bootstrapped.append(&mut plugins);
+    let mut plugins = bootstrapped;
+
+    // Plugins from higher priority directories shadow ones with the same name/hash from lower
+    // priority directories. Ties go to whichever comes first.
+    let mut overridden = Vec::new();
+    if options.override_policy != OverridePolicy::None {
+        let mut winners: HashMap<OverrideKey, usize> = HashMap::with_capacity(plugins.len());
+        for (index, plugin) in plugins.iter().enumerate() {
+            let Some(key) = plugin.override_key(options.override_policy) else { continue };
+            let winner = winners.entry(key).or_insert(index);
+            if plugin.priority > plugins[*winner].priority {
+                *winner = index;
+            }
+        }
+
+        let winning_paths: Vec<Option<PathBuf>> = plugins.iter()
+            .enumerate()
+            .map(|(index, plugin)| {
+                let winner = plugin.override_key(options.override_policy).map(|key| winners[&key])?;
+                (winner != index).then(|| plugins[winner].path.clone())
+            })
+            .collect();
+
+        let mut kept = Vec::with_capacity(plugins.len());
+        for (plugin, winner) in plugins.into_iter().zip(winning_paths) {
+            match winner {
+                Some(winner) => overridden.push((plugin.path, winner)),
+                None => kept.push(plugin),
+            }
+        }
+        plugins = kept;
+    }
+
+    if options.strict {
+        if let Some(index) = plugins.iter().position(|plugin| plugin.nro.is_err()) {
+            let PreparedPlugin { path, nro: Err(error), .. } = plugins.swap_remove(index) else { unreachable!() };
+            return Err(LoaderError::StrictModeViolation { path, source: Box::new(error) });
+        }
+    }
+
+    if let Some(filter) = &options.filter_meta {
+        let mut kept = Vec::with_capacity(plugins.len());
+        for plugin in plugins {
+            if let Ok((nro, _)) = &plugin.nro {
+                if !filter(nro.meta().as_ref()) {
+                    diagnostics.push(Diagnostic::Skipped { path: plugin.path, reason: SkipReason::FilteredByMeta });
+                    continue;
+                }
+            }
+            kept.push(plugin);
+        }
+        plugins = kept;
+    }
+
+    // The same image can't be registered or loaded twice, so only the first copy of a file is kept.
+    let mut deduped = Vec::new();
+    let mut seen: HashMap<Sha256Hash, PathBuf> = HashMap::with_capacity(plugins.len());
+    let mut unique = Vec::with_capacity(plugins.len());
+    for plugin in plugins {
+        if let Ok((_, hash)) = &plugin.nro {
+            if let Some(original) = seen.get(hash) {
+                if options.strict {
+                    return Err(LoaderError::StrictModeViolation {
+                        path: plugin.path,
+                        source: Box::new(LoaderError::DuplicatePlugin { original: original.clone() })
+                    });
+                }
+                deduped.push((plugin.path, original.clone()));
+                continue;
+            }
+            seen.insert(*hash, plugin.path.clone());
+        }
+        unique.push(plugin);
+    }
+    let mut plugins = unique;
+
+    if let Some(registry) = &options.registry {
+        let mut unregistered = Vec::with_capacity(plugins.len());
+        for plugin in plugins {
+            if matches!(&plugin.nro, Ok((_, hash)) if registry.contains(hash)) {
+                quiet.push(Diagnostic::Skipped { path: plugin.path, reason: SkipReason::AlreadyRegistered });
+                continue;
+            }
+            unregistered.push(plugin);
+        }
+        plugins = unregistered;
+    }
+
+    if let Some(budget) = options.memory_budget {
+        let budget = available_code_space().map_or(budget, |available| budget.min(available));
+        plugins = within_budget(plugins, budget, &mut diagnostics);
+    }
+
+    // Informational diagnostics are only reported once modules are mounted, so there are none to
+    // keep if nothing here fails
+    if options.strict {
+        if let Some(error) = diagnostics.drain(..).find_map(Diagnostic::into_error) {
+            return Err(error);
+        }
+    }
+
+    let mut hashes = Vec::with_capacity(plugins.len() + options.extra_hashes.len());
+    hashes.extend(
+        plugins.iter()
+            .filter_map(|plugin| plugin.nro.as_ref().ok())
+            .map(|(_, hash)| *hash)
+    );
+    for hash in options.extra_hashes.iter() {
+        let registered = matches!(&options.registry, Some(registry) if registry.contains(hash));
+        if !hashes.contains(hash) && !registered {
+            hashes.push(*hash);
+        }
+    }
+
+    let nrr_program_id = options.nrr_program_id.unwrap_or(program_id);
+    let retries = if options.strict { 0 } else { options.registration_retries };
+    let template = NrrBuilder::new(nrr_program_id).kind(options.nrr_kind).sort(options.sort_hashes);
+    let mut registrations = match register_chunks(&template, hashes, options.max_hashes_per_nrr, retries) {
+        Ok((registrations, rejected)) => {
+            for (hash, rc) in rejected {
+                let index = plugins.iter()
+                    .position(|plugin| matches!(&plugin.nro, Ok((_, plugin_hash)) if *plugin_hash == hash));
+                match index {
+                    Some(index) => {
+                        let plugin = plugins.remove(index);
+                        diagnostics.push(Diagnostic::Skipped { path: plugin.path, reason: SkipReason::RejectedByNrr { rc } });
+                    },
+                    None => diagnostics.push(Diagnostic::ExtraHashRejected { hash, rc }),
+                }
+            }
+            registrations
+        },
+        Err(LoaderError::RegistrationError(rc)) if nrr_program_id != program_id => {
+            return Err(LoaderError::ProgramIdMismatch { nrr_program_id, program_id, rc });
+        },
+        Err(e) => return Err(e),
+    };
+    if let Some(registry) = &options.registry {
+        for registration in registrations.iter_mut() {
+            registry.insert(&registration.hashes);
+            registration.registry = Some(registry.clone());
+        }
+    }
+
+    let mut modules = Vec::with_capacity(plugins.len());
+    let mut names = Vec::with_capacity(plugins.len());
+    for PreparedPlugin { path, nro, .. } in plugins {
+        let name = match &nro {
+            Ok((nro, _)) => nro.name.clone(),
+            Err(_) => path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        };
+        let module = match nro {
+            Ok((nro, hash)) => match mount_shedding(nro, hash, options, &mut modules, &mut names, &mut diagnostics) {
+                Ok(module) => module,
+                Err(error) => {
+                    rollback(modules, registrations);
+                    return Err(error);
+                },
+            },
+            Err(error) => Err(error),
+        };
+        let module = module.map(|mut module| {
+            module.reader = options.reader();
+            module
+        });
+        if options.on_already_loaded == AlreadyLoadedPolicy::Skip {
+            if let Err(LoaderError::AlreadyLoaded { .. }) = module {
+                diagnostics.push(Diagnostic::Skipped { path, reason: SkipReason::AlreadyLoaded });
+                continue;
+            }
+        }
+        if let Ok(module) = &module {
+            if options.bss_headroom != 0 && module.owns_bss {
+                let (reported, allocated) = (module.bss_size, module.bss_layout.size());
+                quiet.push(Diagnostic::BssPadded { name: name.clone(), reported, allocated });
+            }
+        }
+        if options.strict {
+            if let Err(error) = module {
+                rollback(modules, registrations);
+                return Err(LoaderError::StrictModeViolation { path, source: Box::new(error) });
+            }
+        }
+        modules.push(module);
+        names.push(name);
+    }
+
+    diagnostics.append(&mut quiet);
+
+    if options.unregister_failed {
+        let dangling = dangling_hashes(&registrations, &modules, &options.extra_hashes);
+        if !dangling.is_empty() {
+            registrations = trim_registrations(&template, registrations, &dangling);
+        }
+    }
+
+    Ok(MountInfo {
+        program_id,
+        modules,
+        names,
+        registrations,
+        extra_hashes: options.extra_hashes.clone(),
+        deduped,
+        overridden,
+        diagnostics,
+        preferred_order: None,
+        pinned: HashSet::new(),
+    })
+}
+
+/// Keeps the plugins which fit in `budget` bytes in order, see [`MountOptions::memory_budget`].
+/// Plugins which failed to prepare take up nothing and are always kept.
+fn within_budget(plugins: Vec<PreparedPlugin>, budget: usize, diagnostics: &mut Vec<Diagnostic>) -> Vec<PreparedPlugin> {
+    let mut used = 0;
+    let mut within = Vec::with_capacity(plugins.len());
+    for plugin in plugins {
+        if let Ok((nro, _)) = &plugin.nro {
+            used += nro.footprint();
+            if used > budget {
+                diagnostics.push(Diagnostic::Skipped { path: plugin.path, reason: SkipReason::OverBudget });
+                continue;
+            }
+        }
+        within.push(plugin);
+    }
+    within
+}
+
+/// The hashes registered in `registrations` which neither belong to a mounted module nor were
+/// asked for with [`MountOptions::extra_hashes`], sorted.
+fn dangling_hashes(
+    registrations: &[Registration],
+    modules: &[Result<LoadedModule, LoaderError>],
+    extra_hashes: &[Sha256Hash]
+) -> Vec<Sha256Hash> {
+    let mounted: HashSet<Sha256Hash> = modules.iter().flatten().map(|module| module.hash).collect();
+    let mut hashes: Vec<Sha256Hash> = registrations.iter()
+        .flat_map(|registration| registration.hashes.iter().copied())
+        .filter(|hash| !mounted.contains(hash) && !extra_hashes.contains(hash))
+        .collect();
+    hashes.sort();
+    hashes.dedup();
+    hashes
+}
+
+/// Replaces every NRR holding one of the `dangling` hashes with one holding the rest, see
+/// [`MountOptions::unregister_failed`]. The replacement is registered before the original is
+/// unregistered, so mounted modules are covered throughout. If the replacement can't be
+/// registered the original is kept.
+fn trim_registrations(
+    template: &NrrBuilder,
+    registrations: Vec<Registration>,
+    dangling: &[Sha256Hash]
+) -> Vec<Registration> {
+    registrations.into_iter()
+        .map(|registration| {
+            if !registration.hashes.iter().any(|hash| dangling.contains(hash)) {
+                return registration;
+            }
+            let kept: Vec<Sha256Hash> = registration.hashes.iter()
+                .filter(|hash| !dangling.contains(hash))
+                .copied()
+                .collect();
+            let Ok(mut trimmed) = register_built(&template.clone().hashes(&kept)) else { return registration };
+
+            // The kept hashes are in the registry once for each NRR until the original is gone
+            if let Some(registry) = &registration.registry {
+                registry.insert(&trimmed.hashes);
+                trimmed.registry = Some(registry.clone());
+            }
+            let _ = registration.unregister();
+            trimmed
+        })
+        .collect()
+}
+
+/// Mounts `nro`, unloading the modules [`MountOptions::on_out_of_address_space`] picks for as long
+/// as `LoadModule` runs out of address space. `modules` and `names` are the mount so far, side by
+/// side.
+///
+/// The inner result is the plugin's. The outer one fails if a module couldn't be unloaded, after
+/// which the mount has to be rolled back.
+fn mount_shedding(
+    nro: NroFile,
+    hash: Sha256Hash,
+    options: &MountOptions,
+    modules: &mut Vec<Result<LoadedModule, LoaderError>>,
+    names: &mut Vec<String>,
+    diagnostics: &mut Vec<Diagnostic>
+) -> Result<Result<LoadedModule, LoaderError>, LoaderError> {
+    let Some(shed) = options.shed.as_ref().filter(|_| !options.strict) else { return Ok(nro.mount(hash, options)) };
+    assert_eq!(modules.len(), names.len());
+    loop {
+        // Mounting hands the bytes over, so keep a copy to try again with
+        let error = match nro.clone().mount(hash, options) {
+            Err(error @ LoaderError::OutOfAddressSpace { .. }) => error,
+            result => return Ok(result),
+        };
+
+        let (indices, mounted): (Vec<usize>, Vec<ModuleInfo>) = modules.iter()
+            .enumerate()
+            .filter_map(|(index, module)| Some((index, module.as_ref().ok()?.info())))
+            .unzip();
+        let Some(victim) = pick_victim(&**shed, &mounted) else { return Ok(Err(error)) };
+        let index = indices[victim];
+
+        names.remove(index);
+        let Ok(module) = modules.remove(index) else { unreachable!() };
+        let path = module.path.clone().unwrap_or_else(|| PathBuf::from(&module.name));
+        module.unload()?;
+        diagnostics.push(Diagnostic::Skipped { path, reason: SkipReason::Shed });
+    }
+}
+
+/// The index in `mounted` of the module `shed` picks to unload, or `None` if it doesn't pick one
+/// or picks one which isn't mounted.
+fn pick_victim(shed: &(dyn Fn(&[ModuleInfo]) -> Option<String> + Send + Sync), mounted: &[ModuleInfo]) -> Option<usize> {
+    let victim = shed(mounted)?;
+    mounted.iter().position(|module| module.name == victim)
+}
+
+/// Hashes dropped from an NRR, along with the result code which got them dropped.
+type Rejected = Vec<(Sha256Hash, u32)>;
+
+/// Registers `hashes` in NRRs of at most `chunk_size` hashes each ([`max_modules_per_nrr`] if
+/// `None`), with each NRR recovering from rejected hashes as in [`register_recovering`]. If any
+/// NRR fails, the ones already registered are unregistered again.
+fn register_chunks(
+    template: &NrrBuilder,
+    hashes: Vec<Sha256Hash>,
+    chunk_size: Option<usize>,
+    retries: usize
+) -> Result<(Vec<Registration>, Rejected), LoaderError> {
+    let chunk_size = chunk_size.unwrap_or_else(max_modules_per_nrr).max(1);
+
+    let mut registrations = Vec::new();
+    let mut rejected = Vec::new();
+    // Always register at least one NRR, even if it's empty, as there always has been one
+    for chunk in hashes.chunks(chunk_size).chain(hashes.is_empty().then_some(&[][..])) {
+        let mut chunk = chunk.to_vec();
+        match register_recovering(template, &mut chunk, retries.saturating_sub(rejected.len())) {
+            Ok((registration, mut dropped)) => {
+                registrations.push(registration);
+                rejected.append(&mut dropped);
+            },
+            Err(e) => {
+                rollback(Vec::new(), registrations);
+                return Err(e);
+            }
+        }
+    }
+    Ok((registrations, rejected))
+}
+
+/// Registers `hashes` in an NRR built from `template`, dropping rejected hashes and retrying up to
+/// `retries` times. Returns the registration along with the hashes which were dropped.
+fn register_recovering(
+    template: &NrrBuilder,
+    hashes: &mut Vec<Sha256Hash>,
+    retries: usize
+) -> Result<(Registration, Rejected), LoaderError> {
+    let mut rejected = Vec::new();
+    loop {
+        let error = match register_built(&template.clone().hashes(hashes)) {
+            Ok(registration) => return Ok((registration, rejected)),
+            Err(error) => error,
+        };
+
+        let LoaderError::RegistrationError(rc @ rc::NOT_AUTHORIZED) = error else { return Err(error) };
+        if rejected.len() == retries {
+            return Err(error);
+        }
+        let Some(index) = find_rejected(template, hashes, rc) else { return Err(error) };
+        rejected.push((hashes.remove(index), rc));
+    }
+}
+
+/// Bisects `hashes` (which failed to register with `rc`) for a single hash which fails on its own,
+/// returning its index. Returns `None` if both halves register fine, since then the rejection
+/// isn't down to any one hash.
+fn find_rejected(template: &NrrBuilder, hashes: &[Sha256Hash], rc: u32) -> Option<usize> {
+    let (mut start, mut end) = (0, hashes.len());
+    while end - start > 1 {
+        let middle = start + (end - start) / 2;
+        let fails = |range: std::ops::Range<usize>| match register_built(&template.clone().hashes(&hashes[range])) {
+            Ok(registration) => {
+                let _ = registration.unregister();
+                Some(false)
+            },
+            Err(LoaderError::RegistrationError(probe_rc)) if probe_rc == rc => Some(true),
+            Err(_) => None,
+        };
+
+        if fails(start..middle)? {
+            end = middle;
+        } else if fails(middle..end)? {
+            start = middle;
+        } else {
+            return None;
+        }
+    }
+    (end - start == 1).then_some(start)
+}
+
+fn rollback(modules: Vec<Result<LoadedModule, LoaderError>>, registrations: Vec<Registration>) {
+    for module in modules.into_iter().flatten() {
+        let _ = module.unload();
+    }
+    for registration in registrations {
+        let _ = registration.unregister();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const MAX: usize = MODULE_NAME_SIZE - 1;
+
+    /// A fresh, empty directory for a single test.
+    pub(crate) fn temp_dir(test: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("loader-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    /// A valid NRO, which hashes differently for every `seed`.
+    pub(crate) fn nro_image(seed: u8) -> Vec<u8> {
+        let mut image = self_test::build_image();
+        // Somewhere in the (otherwise empty) data segment
+        image[0x2000] = seed;
+        image
+    }
+
+    fn hash(byte: u8) -> Sha256Hash {
+        Sha256Hash([byte; 0x20])
+    }
+
+    /// A module as if `name` had been mounted, without anything behind it. Only fit for code which
+    /// never hands it to `nn::ro`.
+    pub(crate) fn fake_module(name: &str, byte: u8) -> LoadedModule {
+        LoadedModule {
+            module: unsafe { std::mem::zeroed() },
+            name: name.to_owned(),
+            path: None,
+            hash: hash(byte),
+            image: std::ptr::null_mut(),
+            image_layout: Layout::new::<u8>(),
+            mapped_size: 0,
+            owns_image: false,
+            bss: std::ptr::null_mut(),
+            bss_layout: Layout::new::<u8>(),
+            owns_bss: false,
+            bss_size: 0,
+            mount_duration: None,
+            allocator: Arc::new(Heap),
+            registration: None,
+            hooks: Hooks::default(),
+            settings: MountOptions::default().load_settings(),
+            reader: Reader::default(),
+        }
+    }
+
+    /// Like [`fake_module`], an NRR which was never registered.
+    fn fake_registration(bytes: &[u8]) -> Registration {
+        Registration {
+            info: unsafe { std::mem::zeroed() },
+            image: std::ptr::null_mut(),
+            layout: Layout::new::<u8>(),
+            program_id: 0,
+            hashes: bytes.iter().copied().map(hash).collect(),
+            registry: None,
+        }
+    }
+
+    pub(crate) fn fake_mount(modules: Vec<Result<LoadedModule, LoaderError>>, registrations: Vec<Registration>) -> MountInfo {
+        MountInfo {
+            program_id: 0,
+            names: modules.iter()
+                .map(|module| module.as_ref().map(|module| module.name.clone()).unwrap_or_default())
+                .collect(),
+            modules,
+            registrations,
+            extra_hashes: Vec::new(),
+            deduped: Vec::new(),
+            overridden: Vec::new(),
+            diagnostics: Vec::new(),
+            preferred_order: None,
+            pinned: HashSet::new(),
+        }
+    }
+
+    fn discovered(options: &MountOptions, path: &Path) -> Vec<PathBuf> {
+        let mut plugins: Vec<_> = discover(options, path, |_| true).unwrap()
+            .plugins
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        plugins.sort();
+        plugins
+    }
+
+    #[test]
+    fn short_names_are_kept() {
+        for policy in [NamePolicy::Reject, NamePolicy::Truncate, NamePolicy::Ascii] {
+            assert_eq!(module_name("plugin.nro", policy).unwrap(), "plugin.nro");
+        }
+        assert_eq!(module_name("ünïcode.nro", NamePolicy::Reject).unwrap(), "ünïcode.nro");
+        assert_eq!(module_name("ünïcode.nro", NamePolicy::Truncate).unwrap(), "ünïcode.nro");
+    }
+
+    #[test]
+    fn reject_overlong_names() {
+        let longest = "a".repeat(MAX);
+        assert_eq!(module_name(&longest, NamePolicy::Reject).unwrap(), longest);
+        assert!(check_name(&longest, NamePolicy::Reject).is_ok());
+
+        let overlong = "a".repeat(MAX + 1);
+        let error = module_name(&overlong, NamePolicy::Reject).unwrap_err();
+        assert!(matches!(error, LoaderError::NameTooLong { name, max: MAX } if name == overlong));
+        assert!(check_name(&overlong, NamePolicy::Reject).is_err());
+    }
+
+    #[test]
+    fn truncate_on_char_boundary() {
+        // The two byte 'é' straddles the last byte which fits
+        let name = format!("{}é", "a".repeat(MAX - 1));
+        assert_eq!(name.len(), MAX + 1);
+        assert_eq!(module_name(&name, NamePolicy::Truncate).unwrap(), "a".repeat(MAX - 1));
+
+        // A three byte character ending exactly at the limit is kept
+        let name = format!("{}€{}", "a".repeat(MAX - 3), "b".repeat(10));
+        let truncated = module_name(&name, NamePolicy::Truncate).unwrap();
+        assert_eq!(truncated, format!("{}€", "a".repeat(MAX - 3)));
+        assert_eq!(truncated.len(), MAX);
+
+        // Starting one byte later it no longer fits
+        let name = format!("{}€", "a".repeat(MAX - 2));
+        assert_eq!(module_name(&name, NamePolicy::Truncate).unwrap(), "a".repeat(MAX - 2));
+    }
+
+    #[test]
+    fn ascii_replaces_then_truncates() {
+        assert_eq!(module_name("plügin-€.nro", NamePolicy::Ascii).unwrap(), "pl_gin-_.nro");
+
+        // Every character becomes a single byte, so only then does the name fit
+        let name = "é".repeat(MAX);
+        assert_eq!(module_name(&name, NamePolicy::Ascii).unwrap(), "_".repeat(MAX));
+
+        let name = "é".repeat(MAX + 1);
+        assert_eq!(module_name(&name, NamePolicy::Ascii).unwrap(), "_".repeat(MAX));
+    }
+
+    #[test]
+    fn empty_file_is_invalid() {
+        let dir = temp_dir("empty-file");
+        let path = dir.join("empty.nro");
+        std::fs::write(&path, []).unwrap();
+
+        // The same check `NroFile::prepare` makes before fixing up and hashing the file
+        let nro = NroFile::open(&Reader::default(), &path).unwrap();
+        let error = nro::parse(&nro.data).err().unwrap();
+        assert!(matches!(error, LoaderError::InvalidNro { reason: InvalidNroReason::Empty }));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn directories_are_not_plugins() {
+        let dir = temp_dir("subdirectory");
+        std::fs::create_dir(dir.join("folder.nro")).unwrap();
+        std::fs::write(dir.join("plugin.nro"), [0]).unwrap();
+
+        assert_eq!(discovered(&MountOptions::default(), &dir), [dir.join("plugin.nro")]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_directory() {
+        let dir = temp_dir("missing-directory").join("plugins");
+
+        let error = discover(&MountOptions::default(), &dir, |_| true).err().unwrap();
+        assert!(matches!(error, LoaderError::PluginDirMissing { path } if path == dir));
+
+        let options = MountOptions::default().allow_missing_dir(true);
+        assert!(discovered(&options, &dir).is_empty());
+        std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_mounted_once() {
+        let dir = temp_dir("symlinks");
+        std::fs::write(dir.join("plugin.nro"), [0]).unwrap();
+        std::os::unix::fs::symlink(dir.join("plugin.nro"), dir.join("link.nro")).unwrap();
+        // A link back to the directory itself isn't a regular file, so it isn't followed
+        std::os::unix::fs::symlink(&dir, dir.join("loop")).unwrap();
+
+        let discovered = discover(&MountOptions::default(), &dir, |_| true).unwrap();
+        assert_eq!(discovered.plugins.len(), 1);
+        assert_eq!(discovered.duplicates.len(), 1);
+
+        let mut paths: Vec<_> = discovered.plugins.into_iter()
+            .map(|(path, _)| path)
+            .chain(discovered.duplicates)
+            .collect();
+        paths.sort();
+        assert_eq!(paths, [dir.join("link.nro"), dir.join("plugin.nro")]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn linked_directories_are_deduplicated() {
+        let dir = temp_dir("linked-directories");
+        std::fs::write(dir.join("plugin.nro"), [0]).unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("loop")).unwrap();
+
+        // The same directory reached again through the self-referential link
+        let options = MountOptions::default().directories(vec![(dir.join("loop"), Priority::default())]);
+        let discovered = discover(&options, &dir, |_| true).unwrap();
+        assert_eq!(discovered.plugins, [(dir.join("plugin.nro"), Priority::default())]);
+        assert_eq!(discovered.duplicates, [dir.join("loop").join("plugin.nro")]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_modules_leave_dangling_hashes() {
+        let failed = LoaderError::MountError { rc: rc::INVALID_NRO, image: None, relocations_total: None };
+        let modules = vec![Ok(fake_module("a", 1)), Err(failed), Ok(fake_module("c", 3))];
+        let mut info = fake_mount(modules, vec![fake_registration(&[3, 2, 1]), fake_registration(&[4, 2])]);
+        assert_eq!(info.dangling_hashes(), [hash(2), hash(4)]);
+
+        // Extra hashes are registered on purpose
+        info.extra_hashes = vec![hash(4)];
+        assert_eq!(info.dangling_hashes(), [hash(2)]);
+    }
+
+    #[test]
+    fn pinning() {
+        let mut info = fake_mount(vec![Ok(fake_module("core", 1)), Ok(fake_module("extra", 2))], Vec::new());
+        assert!(matches!(info.pin("missing"), Err(LoaderError::UnknownModule { name }) if name == "missing"));
+
+        info.pin("core").unwrap();
+        assert!(info.is_pinned("core"));
+        assert!(!info.is_pinned("extra"));
+        assert!(info.unpin("core"));
+        assert!(!info.unpin("core"));
+        assert!(!info.is_pinned("core"));
+    }
+
+    fn prepared(name: &str, size: usize, byte: u8) -> PreparedPlugin {
+        let nro = NroFile { data: vec![0; size], name: name.to_owned(), path: Some(PathBuf::from(name)) };
+        PreparedPlugin { path: PathBuf::from(name), priority: Priority::default(), nro: Ok((nro, hash(byte))) }
+    }
+
+    #[test]
+    fn budget_only_fits_the_first_plugin() {
+        let failed = PreparedPlugin {
+            path: PathBuf::from("failed.nro"),
+            priority: Priority::default(),
+            nro: Err(LoaderError::InvalidNro { reason: InvalidNroReason::Empty }),
+        };
+        let plugins = vec![
+            prepared("first.nro", 0x1800, 1),
+            failed,
+            prepared("second.nro", 0x1000, 2),
+            prepared("third.nro", 0x10, 3),
+        ];
+
+        let mut diagnostics = Vec::new();
+        let kept: Vec<_> = within_budget(plugins, 0x2800, &mut diagnostics).into_iter()
+            .map(|plugin| plugin.path)
+            .collect();
+        assert_eq!(kept, [PathBuf::from("first.nro"), PathBuf::from("failed.nro")]);
+
+        // Once one plugin doesn't fit, smaller ones after it are skipped too
+        let skipped: Vec<_> = diagnostics.into_iter()
+            .map(|diagnostic| match diagnostic {
+                Diagnostic::Skipped { path, reason: SkipReason::OverBudget } => path,
+                _ => panic!("unexpected diagnostic"),
+            })
+            .collect();
+        assert_eq!(skipped, [PathBuf::from("second.nro"), PathBuf::from("third.nro")]);
+    }
+
+    #[test]
+    fn symbol_names_are_checked() {
+        assert_eq!(symbol_name("init").unwrap().as_bytes(), b"init");
+        for name in ["", "in\0it", "init\0"] {
+            let error = symbol_name(name).unwrap_err();
+            assert!(matches!(error, LoaderError::InvalidSymbolName { name: invalid } if invalid == name));
+        }
+    }
+
+    #[test]
+    fn lookup_results() {
+        assert_eq!(lookup_result("init", 0, 0x1234).unwrap(), 0x1234 as *const ());
+        let error = lookup_result("init", 0, 0).unwrap_err();
+        assert!(matches!(error, LoaderError::SymbolNotFound { name } if name == "init"));
+        // A failed lookup isn't a missing symbol, whatever the address was left at
+        let error = lookup_result("init", rc::NOT_LOADED, 0x1234).unwrap_err();
+        assert!(matches!(error, LoaderError::LookupError { name, rc: rc::NOT_LOADED } if name == "init"));
+    }
+
+    #[test]
+    fn lookup_through_nn_ro() {
+        let image = nro_image(1);
+        let registration = register_nrr(0, &[Sha256Hash::new(&image)]).unwrap();
+        let module = mount_nro(&image, "plugin", &MountOptions::default()).unwrap();
+        ro::with(|state| state.symbols.push(("init".to_owned(), 0x10)));
+
+        assert_eq!(module.lookup_symbol("init").unwrap(), unsafe { module.base().add(0x10) } as *const ());
+        assert!(matches!(module.lookup_symbol("update"), Err(LoaderError::SymbolNotFound { .. })));
+        assert!(matches!(module.lookup_symbol(""), Err(LoaderError::InvalidSymbolName { .. })));
+        assert_eq!(module.symbol("update"), None);
+
+        module.unload().unwrap();
+        registration.unregister().unwrap();
+    }
+
+    fn exports(name: &str) -> Result<*const (), LoaderError> {
+        match name {
+            "version" => Ok(0x100 as *const ()),
+            "init" => Ok(0x200 as *const ()),
+            "update" => Ok(0x300 as *const ()),
+            _ => Err(LoaderError::SymbolNotFound { name: name.to_owned() }),
+        }
+    }
+
+    #[test]
+    fn resolve_in_order() {
+        let addresses = resolve_with(&["update", "version", "init"], exports).unwrap();
+        assert_eq!(addresses, [0x300 as *const (), 0x100 as *const (), 0x200 as *const ()]);
+        assert!(resolve_with(&[], exports).unwrap().is_empty());
+    }
+
+    #[test]
+    fn resolve_reports_the_missing_symbol() {
+        let mut looked_up = Vec::new();
+        let error = resolve_with(&["version", "shutdown", "init"], |name| {
+            looked_up.push(name.to_owned());
+            exports(name)
+        }).unwrap_err();
+        assert!(matches!(error, LoaderError::SymbolNotFound { name } if name == "shutdown"));
+        // Nothing after the missing symbol is looked up
+        assert_eq!(looked_up, ["version", "shutdown"]);
+    }
+
+    #[test]
+    fn resolve_through_nn_ro() {
+        let image = nro_image(1);
+        let registration = register_nrr(0, &[Sha256Hash::new(&image)]).unwrap();
+        let module = mount_nro(&image, "plugin", &MountOptions::default()).unwrap();
+        ro::with(|state| {
+            state.symbols.push(("init".to_owned(), 0x10));
+            state.symbols.push(("version".to_owned(), 0x20));
+        });
+
+        let base = module.base();
+        let addresses = module.resolve_all(&["version", "init"]).unwrap();
+        assert_eq!(addresses, unsafe { [base.add(0x20) as *const (), base.add(0x10) as *const ()] });
+        let error = module.resolve_all(&["init", "update"]).unwrap_err();
+        assert!(matches!(error, LoaderError::SymbolNotFound { name } if name == "update"));
+
+        module.unload().unwrap();
+        registration.unregister().unwrap();
+    }
+
+    fn mounted_at(name: &str, base: usize) -> ModuleInfo<'_> {
+        ModuleInfo { name, path: None, hash: hash(0), base }
+    }
+
+    fn lowest(mounted: &[ModuleInfo]) -> Option<String> {
+        mounted.iter().min_by_key(|module| module.base).map(|module| module.name.to_owned())
+    }
+
+    #[test]
+    fn pick_shed_victims() {
+        let mounted = [mounted_at("a.nro", 0x3000), mounted_at("b.nro", 0x1000), mounted_at("c.nro", 0x2000)];
+        assert_eq!(pick_victim(&lowest, &mounted), Some(1));
+        assert_eq!(pick_victim(&lowest, &[]), None);
+        assert_eq!(pick_victim(&|_: &[ModuleInfo]| None, &mounted), None);
+        // Naming a module which isn't mounted frees nothing
+        assert_eq!(pick_victim(&|_: &[ModuleInfo]| Some("d.nro".to_owned()), &mounted), None);
+    }
+
+    fn shedding_dir(test: &str) -> PathBuf {
+        let dir = temp_dir(test);
+        for (seed, name) in [(1, "a.nro"), (2, "b.nro"), (3, "c.nro")] {
+            std::fs::write(dir.join(name), nro_image(seed)).unwrap();
+        }
+        // Room for two modules, until one is unloaded
+        ro::with(|state| state.fail_load = Some((2, rc::OUT_OF_ADDRESS_SPACE)));
+        dir
+    }
+
+    #[test]
+    fn shedding_frees_room() {
+        let dir = shedding_dir("shedding");
+        let options = MountOptions::default()
+            .load_order(LoadOrder::FileName)
+            .on_out_of_address_space(|mounted| mounted.first().map(|module| module.name.to_owned()));
+
+        let info = mount_from_directory_with_options(0, &dir, |_| true, &options).unwrap();
+        assert_eq!(info.names, ["b.nro", "c.nro"]);
+        assert!(info.modules.iter().all(Result::is_ok));
+        assert!(matches!(
+            &info.diagnostics[..],
+            [Diagnostic::Skipped { path, reason: SkipReason::Shed }] if *path == dir.join("a.nro")
+        ));
+        assert_eq!(ro::counts(), (1, 2));
+
+        info.unload_all().unwrap();
+        assert_eq!(ro::counts(), (0, 0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn strict_mode_does_not_shed() {
+        let dir = shedding_dir("shedding-strict");
+        let shed = Arc::new(AtomicUsize::new(0));
+        let calls = shed.clone();
+        let options = MountOptions::default()
+            .load_order(LoadOrder::FileName)
+            .strict(true)
+            .on_out_of_address_space(move |mounted| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                mounted.first().map(|module| module.name.to_owned())
+            });
+
+        let error = mount_from_directory_with_options(0, &dir, |_| true, &options).err().unwrap();
+        assert!(matches!(
+            error,
+            LoaderError::StrictModeViolation { path, source } if path == dir.join("c.nro")
+                && matches!(*source, LoaderError::OutOfAddressSpace { .. })
+        ));
+        assert_eq!(shed.load(Ordering::Relaxed), 0);
+        assert_eq!(ro::counts(), (0, 0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn informational_diagnostics_are_not_errors() {
+        let padded = Diagnostic::BssPadded { name: "a.nro".to_owned(), reported: 0x1000, allocated: 0x2000 };
+        assert!(padded.into_error().is_none());
+
+        let skipped = Diagnostic::Skipped { path: PathBuf::from("a.nro"), reason: SkipReason::OverBudget };
+        assert!(matches!(
+            skipped.into_error(),
+            Some(LoaderError::StrictModeViolation { source, .. }) if matches!(*source, LoaderError::Skipped(SkipReason::OverBudget))
+        ));
+    }
+
+    /// A trivial codec, which stores the NRO as is behind a header of its own.
+    fn unpack(data: &[u8]) -> Result<Vec<u8>, LoaderError> {
+        match data.strip_prefix(b"PACK") {
+            Some(body) => Ok(body.to_vec()),
+            None => Err(LoaderError::InvalidNro { reason: InvalidNroReason::BadMagic }),
+        }
+    }
+
+    fn packed(image: &[u8]) -> Vec<u8> {
+        [b"PACK", image].concat()
+    }
+
+    #[test]
+    fn packed_plugins_are_inflated() {
+        let dir = temp_dir("packed");
+        std::fs::write(dir.join("a.nro"), packed(&nro_image(1))).unwrap();
+        std::fs::write(dir.join("b.nro"), nro_image(2)).unwrap();
+        std::fs::write(dir.join("garbage.nro"), b"nope").unwrap();
+        let options = MountOptions::default().decompressor(unpack);
+        let reader = options.reader();
+
+        assert_eq!(NroFile::open(&reader, dir.join("a.nro")).unwrap().data, nro_image(1));
+        // Plain NROs don't go through the codec, which would reject them
+        assert_eq!(NroFile::open(&reader, dir.join("b.nro")).unwrap().data, nro_image(2));
+        let error = NroFile::open(&reader, dir.join("garbage.nro")).err().unwrap();
+        assert!(matches!(error, LoaderError::InvalidNro { reason: InvalidNroReason::BadMagic }));
+
+        // The hash is the one the plain NRO would get
+        let (_, hash) = NroFile::prepare(&reader, dir.join("a.nro")).unwrap();
+        let mut image = nro_image(1);
+        nro::fix_bss_size(&mut image).unwrap();
+        assert_eq!(hash, Sha256Hash::new(&image));
+
+        std::fs::remove_file(dir.join("garbage.nro")).unwrap();
+        let info = mount_from_directory_with_options(0, &dir, |_| true, &options).unwrap();
+        assert!(info.modules.iter().all(Result::is_ok));
+        assert_eq!(ro::counts(), (1, 2));
+        info.unload_all().unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn packed_plugins_with_bad_sizes() {
+        let mut truncated = nro_image(1);
+        truncated.truncate(truncated.len() / 2);
+        let mut oversized = nro_image(1);
+        oversized[0x18..0x1c].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let dir = temp_dir("packed-sizes");
+        let reader = MountOptions::default().decompressor(unpack).reader();
+        for (name, image) in [("truncated.nro", truncated), ("oversized.nro", oversized)] {
+            let path = dir.join(name);
+            std::fs::write(&path, packed(&image)).unwrap();
+            let error = NroFile::prepare(&reader, &path).err().unwrap();
+            assert!(matches!(error, LoaderError::InvalidNro { reason: InvalidNroReason::Truncated { .. } }), "{}", name);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// The module mounted from `a.nro` in `dir`, and the NRR it was mounted with.
+    fn mounted_from(dir: &Path) -> (LoadedModule, Vec<Registration>) {
+        let (mut modules, registrations) = mount_from_directory(0, dir, |_| true).unwrap().into_parts();
+        let (_, module) = modules.remove(0);
+        (module.unwrap(), registrations)
+    }
+
+    #[test]
+    fn reloading_keeps_one_registration() {
+        let dir = temp_dir("reload");
+        std::fs::write(dir.join("a.nro"), nro_image(1)).unwrap();
+        let (mut module, registrations) = mounted_from(&dir);
+        assert_eq!(ro::counts(), (1, 1));
+
+        // The directory's NRR stays, and each reload's NRR replaces the previous one
+        for seed in 2..6 {
+            std::fs::write(dir.join("a.nro"), nro_image(seed)).unwrap();
+            module = module.reload(0).unwrap();
+            assert_eq!(ro::counts(), (2, 1));
+        }
+        // Reloading an unchanged file registers its hash again, while the old version is loaded
+        module = module.reload(0).unwrap();
+        assert_eq!(ro::counts(), (2, 1));
+
+        // A version which fails to load leaves nothing behind
+        ro::with(|state| state.fail_load = Some((0, rc::INVALID_NRO)));
+        assert!(matches!(module.reload(0), Err(LoaderError::MountError { .. })));
+        assert_eq!(ro::counts(), (1, 0));
+        for registration in registrations {
+            registration.unregister().unwrap();
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_reload_unloads() {
+        let dir = temp_dir("reload-unreadable");
+        std::fs::write(dir.join("a.nro"), nro_image(1)).unwrap();
+        let (module, registrations) = mounted_from(&dir);
+
+        std::fs::write(dir.join("a.nro"), b"nope").unwrap();
+        assert!(matches!(module.reload(0), Err(LoaderError::InvalidNro { .. })));
+        assert_eq!(ro::counts(), (1, 0));
+        for registration in registrations {
+            registration.unregister().unwrap();
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// The heap, except that the `nth` allocation (from 0) of exactly `size` bytes fails.
+    struct FailingAllocator {
+        size: usize,
+        nth: usize,
+        seen: AtomicUsize,
+        live: AtomicUsize,
+    }
+
+    impl FailingAllocator {
+        fn new(size: usize, nth: usize) -> Arc<Self> {
+            Arc::new(Self { size, nth, seen: AtomicUsize::new(0), live: AtomicUsize::new(0) })
+        }
+
+        fn live(&self) -> usize {
+            self.live.load(Ordering::Relaxed)
+        }
+    }
+
+    unsafe impl Allocator for FailingAllocator {
+        fn allocate(&self, layout: Layout) -> *mut u8 {
+            if layout.size() == self.size && self.seen.fetch_add(1, Ordering::Relaxed) == self.nth {
+                return std::ptr::null_mut();
+            }
+            self.live.fetch_add(1, Ordering::Relaxed);
+            Heap.allocate(layout)
+        }
+
+        unsafe fn free(&self, memory: *mut u8, layout: Layout) {
+            self.live.fetch_sub(1, Ordering::Relaxed);
+            Heap.free(memory, layout);
+        }
+    }
+
+    /// Four plugins, with images of 0x3000 bytes and a BSS of 0x1000 bytes each.
+    fn four_plugins(test: &str) -> PathBuf {
+        let dir = temp_dir(test);
+        for (seed, name) in [(1, "a.nro"), (2, "b.nro"), (3, "c.nro"), (4, "d.nro")] {
+            std::fs::write(dir.join(name), nro_image(seed)).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn failed_bss_allocation_fails_its_module() {
+        let dir = four_plugins("bss-allocation");
+        let allocator = FailingAllocator::new(0x1000, 2);
+        let options = MountOptions::default().load_order(LoadOrder::FileName).allocator(allocator.clone());
+
+        let info = mount_from_directory_with_options(0, &dir, |_| true, &options).unwrap();
+        assert!(matches!(
+            &info.modules[..],
+            [Ok(_), Ok(_), Err(LoaderError::OutOfMemory { size: 0x1000 }), Ok(_)]
+        ));
+        assert_eq!(info.names, ["a.nro", "b.nro", "c.nro", "d.nro"]);
+        // c.nro's image was freed again, and the NRR still covers the others
+        assert_eq!(allocator.live(), 6);
+        assert_eq!(ro::counts(), (1, 3));
+
+        info.unload_all().unwrap();
+        assert_eq!(allocator.live(), 0);
+        assert_eq!(ro::counts(), (0, 0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_bss_allocation_rolls_back_in_strict_mode() {
+        let dir = four_plugins("bss-allocation-strict");
+        let allocator = FailingAllocator::new(0x1000, 2);
+        let options = MountOptions::default()
+            .load_order(LoadOrder::FileName)
+            .allocator(allocator.clone())
+            .strict(true);
+
+        let error = mount_from_directory_with_options(0, &dir, |_| true, &options).err().unwrap();
+        assert!(matches!(
+            error,
+            LoaderError::StrictModeViolation { path, source } if path == dir.join("c.nro")
+                && matches!(*source, LoaderError::OutOfMemory { size: 0x1000 })
+        ));
+        // a.nro and b.nro were unloaded again, and nothing was left allocated
+        assert_eq!(allocator.live(), 0);
+        assert_eq!(ro::counts(), (0, 0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    discover, mount_from_directory_with_options, mount_with_own_nrr, scan_diff, LoadedModule, LoaderError,
+    ModuleInfo, MountInfo, MountOptions, NroFile, ScanDiff,
+};
+
+type Validator = Box<dyn Fn(&Path) -> bool + Send + Sync>;
+type EvictHook = Box<dyn FnMut(&ModuleInfo) + Send + Sync>;
+
+/// When [`PluginManager::add`] should unload plugins to make room, see [`PluginManager::lru`].
+///
+/// Plugins count as used when they're added, reloaded, passed to [`PluginManager::touch`] or
+/// found by [`PluginManager::lookup_symbol`]. The plugins mounted up front start out unused, so
+/// they're the first to go, in load order.
+#[derive(Default)]
+pub struct LruPolicy {
+    max_modules: Option<usize>,
+    max_memory: Option<usize>,
+    on_evict: Option<EvictHook>,
+}
+
+impl LruPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The most modules to keep mounted at once.
+    pub fn max_modules(mut self, count: usize) -> Self {
+        self.max_modules = Some(count);
+        self
+    }
+
+    /// The most memory the mounted modules may use, going by [`LoadedModule::footprint`].
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    /// Called just before a plugin is unloaded to make room.
+    pub fn on_evict<F: FnMut(&ModuleInfo) + Send + Sync + 'static>(mut self, hook: F) -> Self {
+        self.on_evict = Some(Box::new(hook));
+        self
+    }
+
+    fn exceeded(&self, count: usize, memory: usize) -> bool {
+        matches!(self.max_modules, Some(max) if count > max) || matches!(self.max_memory, Some(max) if memory > max)
+    }
+}
+
+/// Mounts a plugin directory and keeps it mounted: modules can be reloaded or unloaded one at a
+/// time, and everything is unloaded again with [`PluginManager::shutdown`].
+///
+/// This is the easiest way to use the loader. It's built on [`mount_from_directory_with_options`],
+/// [`LoadedModule::reload`] and [`MountInfo::unload_all`], which can be used directly when more
+/// control is needed.
+pub struct PluginManager {
+    program_id: u64,
+    directory: PathBuf,
+    validator: Validator,
+    options: MountOptions,
+    info: MountInfo,
+    lru: Option<LruPolicy>,
+    // When each module was last used, going by `clock`
+    last_used: HashMap<String, u64>,
+    clock: u64,
+}
+
+impl PluginManager {
+    /// Mounts the plugins in `directory` which pass `validator`.
+    pub fn new<P, F>(program_id: u64, directory: P, validator: F, options: MountOptions) -> Result<Self, LoaderError>
+    where
+        P: Into<PathBuf>,
+        F: Fn(&Path) -> bool + Send + Sync + 'static
+    {
+        let directory = directory.into();
+        let info = mount_from_directory_with_options(program_id, &directory, &validator, &options)?;
+        Ok(Self {
+            program_id,
+            directory,
+            validator: Box::new(validator),
+            options,
+            info,
+            lru: None,
+            last_used: HashMap::new(),
+            clock: 0,
+        })
+    }
+
+    /// Unloads the least recently used plugins whenever [`PluginManager::add`] would go over
+    /// `policy`'s limits. Pinned modules (see [`MountInfo::pin`]) are never unloaded.
+    pub fn lru(mut self, policy: LruPolicy) -> Self {
+        self.lru = Some(policy);
+        self
+    }
+
+    /// The successfully mounted modules, in load order.
+    pub fn list(&self) -> impl Iterator<Item = &LoadedModule> {
+        self.info.iter_load_order()
+    }
+
+    /// The result of the mount, including the modules which failed and the mount's diagnostics.
+    pub fn info(&self) -> &MountInfo {
+        &self.info
+    }
+
+    /// Mounts the plugin at `path` under an NRR of its own, first unloading the least recently used
+    /// plugins if that would go over the [`LruPolicy`]. If only pinned plugins are left, it's
+    /// mounted anyway.
+    pub fn add<P: AsRef<Path>>(&mut self, path: P) -> Result<&LoadedModule, LoaderError> {
+        let reader = self.options.reader();
+        let (nro, hash) = NroFile::prepare(&reader, path.as_ref())?;
+        self.make_room(nro.footprint())?;
+
+        let mut module = mount_with_own_nrr(self.nrr_program_id(), nro, hash, &self.options)?;
+        module.reader = reader;
+        self.touch(module.name());
+        self.info.names.push(module.name().to_owned());
+        self.info.modules.push(Ok(module));
+        Ok(self.info.modules.last().unwrap().as_ref().unwrap())
+    }
+
+    /// Marks the module called `name` as just used, for the [`LruPolicy`].
+    pub fn touch(&mut self, name: &str) {
+        self.clock += 1;
+        self.last_used.insert(name.to_owned(), self.clock);
+    }
+
+    /// Looks `symbol` up in each mounted module in load order, see [`LoadedModule::symbol`], and
+    /// marks the module exporting it as used.
+    pub fn lookup_symbol(&mut self, symbol: &str) -> Option<*const ()> {
+        let (name, address) = self.info.iter_load_order()
+            .find_map(|module| Some((module.name().to_owned(), module.symbol(symbol)?)))?;
+        self.touch(&name);
+        Some(address)
+    }
+
+    /// Reloads the module called `name` from its file, see [`LoadedModule::reload`].
+    ///
+    /// If the reload fails the module is forgotten, as the old version is gone by then.
+    pub fn reload(&mut self, name: &str) -> Result<&LoadedModule, LoaderError> {
+        let index = self.position(|module| module.name() == name)
+            .ok_or_else(|| LoaderError::UnknownModule { name: name.to_owned() })?;
+        self.reload_at(index)
+    }
+
+    /// Unloads the module called `name` and forgets about it. Its hash stays registered until
+    /// [`PluginManager::shutdown`], as it's shared with the other modules.
+    pub fn unload(&mut self, name: &str) -> Result<(), LoaderError> {
+        let index = self.position(|module| module.name() == name)
+            .ok_or_else(|| LoaderError::UnknownModule { name: name.to_owned() })?;
+        self.unload_at(index)
+    }
+
+    /// Scans the directory again and brings the mounted modules in line with it: modules whose
+    /// file changed are reloaded, modules whose file is gone are unloaded, and new files are
+    /// mounted, each under an NRR of their own. Returns what changed.
+    ///
+    /// Pinned modules (see [`MountInfo::pin`]) are left alone even if their file changed or is
+    /// gone. Files which can't be read during the scan are treated as unchanged. Modules which fail to
+    /// reload or mount are recorded as failed in [`MountInfo::modules`] rather than failing the
+    /// whole call, which only fails if the directory can't be listed or a module can't be unloaded.
+    pub fn reload_changed(&mut self) -> Result<ScanDiff, LoaderError> {
+        let reader = self.options.reader();
+        let old = self.info.scan();
+        let new: Vec<_> = discover(&self.options, &self.directory, &self.validator)?
+            .plugins
+            .into_iter()
+            .filter_map(|(path, _)| match NroFile::prepare(&reader, &path) {
+                Ok((_, hash)) => Some((path, hash)),
+                Err(_) => old.iter().find(|(old_path, _)| *old_path == path).cloned(),
+            })
+            .collect();
+
+        let diff = scan_diff(&old, &new);
+        for path in diff.removed.iter() {
+            if let Some(index) = self.unpinned_position(path) {
+                self.unload_at(index)?;
+            }
+        }
+        for path in diff.modified.iter() {
+            if let Some(index) = self.unpinned_position(path) {
+                let name = self.info.names.get(index).cloned().unwrap_or_default();
+                if let Err(e) = self.reload_at(index) {
+                    self.info.names.insert(index.min(self.info.names.len()), name);
+                    self.info.modules.insert(index, Err(e));
+                }
+            }
+        }
+        for path in diff.added.iter() {
+            let module = NroFile::prepare(&reader, path).and_then(|(nro, hash)| {
+                let mut module = mount_with_own_nrr(self.nrr_program_id(), nro, hash, &self.options)?;
+                module.reader = reader.clone();
+                Ok(module)
+            });
+            let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            self.info.names.push(name);
+            self.info.modules.push(module);
+        }
+        Ok(diff)
+    }
+
+    /// Unloads every module and unregisters the NRRs, see [`MountInfo::unload_all`].
+    pub fn shutdown(self) -> Result<(), LoaderError> {
+        self.info.unload_all()
+    }
+
+    // What the initial mount registered under, see `MountOptions::nrr_program_id`
+    fn nrr_program_id(&self) -> u64 {
+        self.options.nrr_program_id.unwrap_or(self.program_id)
+    }
+
+    fn make_room(&mut self, footprint: usize) -> Result<(), LoaderError> {
+        loop {
+            let count = self.info.iter_load_order().count() + 1;
+            let memory = self.info.iter_load_order().map(LoadedModule::footprint).sum::<usize>() + footprint;
+            if !matches!(&self.lru, Some(lru) if lru.exceeded(count, memory)) {
+                return Ok(());
+            }
+
+            let Some(victim) = self.lru_victim() else { return Ok(()) };
+
+            let index = self.position(|module| module.name() == victim).unwrap();
+            let hook = self.lru.as_mut().and_then(|lru| lru.on_evict.as_mut());
+            if let (Some(hook), Ok(module)) = (hook, &self.info.modules[index]) {
+                hook(&module.info());
+            }
+            self.unload_at(index)?;
+        }
+    }
+
+    // The least recently used unpinned module. Ties (i.e. modules which were never used) go to the
+    // earliest loaded.
+    fn lru_victim(&self) -> Option<String> {
+        self.info.iter_load_order()
+            .filter(|module| !self.info.is_pinned(module.name()))
+            .min_by_key(|module| self.last_used.get(module.name()).copied().unwrap_or(0))
+            .map(|module| module.name().to_owned())
+    }
+
+    fn unpinned_position(&self, path: &Path) -> Option<usize> {
+        self.position(|module| module.path() == Some(path) && !self.info.is_pinned(module.name()))
+    }
+
+    fn position<F: Fn(&LoadedModule) -> bool>(&self, predicate: F) -> Option<usize> {
+        self.info.modules.iter().position(|module| matches!(module, Ok(module) if predicate(module)))
+    }
+
+    // `index` has to be a mounted module, as found by `position`
+    fn reload_at(&mut self, index: usize) -> Result<&LoadedModule, LoaderError> {
+        let module = std::mem::replace(&mut self.info.modules[index], Err(LoaderError::NotReloadable))?;
+        match module.reload(self.nrr_program_id()) {
+            Ok(module) => {
+                self.touch(module.name());
+                self.info.modules[index] = Ok(module);
+                Ok(self.info.modules[index].as_ref().unwrap())
+            },
+            Err(e) => {
+                let _ = self.forget(index);
+                Err(e)
+            }
+        }
+    }
+
+    fn unload_at(&mut self, index: usize) -> Result<(), LoaderError> {
+        match self.forget(index) {
+            Ok(module) => {
+                self.info.unpin(module.name());
+                self.last_used.remove(module.name());
+                module.unload()
+            },
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn forget(&mut self, index: usize) -> Result<LoadedModule, LoaderError> {
+        if index < self.info.names.len() {
+            self.info.names.remove(index);
+        }
+        self.info.modules.remove(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ro, Sha256Hash};
+    use crate::tests::{fake_module, fake_mount, nro_image, temp_dir};
+
+    fn manager(names: &[&str]) -> PluginManager {
+        let modules = names.iter().zip(1..).map(|(name, byte)| Ok(fake_module(name, byte))).collect();
+        PluginManager {
+            program_id: 0,
+            directory: PathBuf::new(),
+            validator: Box::new(|_| true),
+            options: MountOptions::default(),
+            info: fake_mount(modules, Vec::new()),
+            lru: None,
+            last_used: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let policy = LruPolicy::new().max_modules(2).max_memory(0x2000);
+        assert!(!policy.exceeded(2, 0x2000));
+        assert!(policy.exceeded(3, 0x2000));
+        assert!(policy.exceeded(2, 0x2001));
+        assert!(!LruPolicy::new().exceeded(usize::MAX, usize::MAX));
+    }
+
+    #[test]
+    fn unused_modules_go_first_in_load_order() {
+        let mut manager = manager(&["a", "b", "c"]);
+        assert_eq!(manager.lru_victim().as_deref(), Some("a"));
+        manager.touch("a");
+        assert_eq!(manager.lru_victim().as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn eviction_order() {
+        let mut manager = manager(&["a", "b", "c"]);
+        manager.touch("c");
+        manager.touch("a");
+        manager.touch("b");
+        assert_eq!(manager.lru_victim().as_deref(), Some("c"));
+        manager.touch("c");
+        assert_eq!(manager.lru_victim().as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn pinned_modules_are_kept() {
+        let mut manager = manager(&["a", "b"]);
+        manager.info.pin("a").unwrap();
+        manager.touch("b");
+        assert_eq!(manager.lru_victim().as_deref(), Some("b"));
+        manager.info.pin("b").unwrap();
+        assert_eq!(manager.lru_victim(), None);
+    }
+
+    fn names(manager: &PluginManager) -> Vec<&str> {
+        manager.list().map(LoadedModule::name).collect()
+    }
+
+    #[test]
+    fn lifecycle() {
+        let dir = temp_dir("manager-lifecycle");
+        std::fs::write(dir.join("a.nro"), nro_image(1)).unwrap();
+        std::fs::write(dir.join("b.nro"), nro_image(2)).unwrap();
+        let extra = temp_dir("manager-lifecycle-extra").join("c.nro");
+        std::fs::write(&extra, nro_image(3)).unwrap();
+
+        let options = MountOptions::default().load_order(crate::LoadOrder::FileName);
+        let mut manager = PluginManager::new(0x0100_0000_0000_1234, &dir, |_| true, options).unwrap();
+        assert_eq!(names(&manager), ["a.nro", "b.nro"]);
+        assert_eq!(ro::counts(), (1, 2));
+
+        // Added plugins get an NRR of their own
+        manager.add(&extra).unwrap();
+        assert_eq!(names(&manager), ["a.nro", "b.nro", "c.nro"]);
+        assert_eq!(ro::counts(), (2, 3));
+
+        // A reloaded plugin is registered again under a new NRR, which its next reload replaces
+        std::fs::write(dir.join("a.nro"), nro_image(4)).unwrap();
+        let hash = manager.reload("a.nro").unwrap().hash();
+        let mut image = nro_image(4);
+        crate::nro::fix_bss_size(&mut image).unwrap();
+        assert_eq!(hash, Sha256Hash::new(&image));
+        assert_eq!(ro::counts(), (3, 3));
+        manager.reload("a.nro").unwrap();
+        assert_eq!(ro::counts(), (3, 3));
+        assert_eq!(names(&manager), ["a.nro", "b.nro", "c.nro"]);
+
+        manager.unload("b.nro").unwrap();
+        assert_eq!(names(&manager), ["a.nro", "c.nro"]);
+        assert_eq!(ro::counts(), (3, 2));
+        assert!(matches!(manager.unload("b.nro"), Err(LoaderError::UnknownModule { .. })));
+
+        manager.shutdown().unwrap();
+        assert_eq!(ro::counts(), (0, 0));
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(extra.parent().unwrap()).unwrap();
+    }
+}
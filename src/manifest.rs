@@ -0,0 +1,174 @@
+//! A compact binary record of what was mounted, for attaching to crash and bug reports, and a
+//! readable text version of it.
+
+use std::io::Write;
+
+use crate::{LoaderError, MountInfo, Sha256Hash};
+
+const MANIFEST_MAGIC: &[u8; 4] = b"LMAN";
+const MANIFEST_VERSION: u32 = 1;
+
+impl MountInfo {
+    /// Serializes the program id, the set fingerprint and the hash, base address and sizes of every
+    /// mounted module, so support tooling can tell which plugins were active.
+    ///
+    /// All integers are little-endian. The layout is:
+    ///
+    /// | Size     | Field                                           |
+    /// |----------|-------------------------------------------------|
+    /// | 4        | Magic, `LMAN`                                   |
+    /// | 4        | Version, currently 1                            |
+    /// | 8        | Program id                                      |
+    /// | 0x20     | [`MountInfo::set_fingerprint`]                  |
+    /// | 4        | Module count                                    |
+    /// | Variable | One entry per module, in load order             |
+    ///
+    /// and each module entry is:
+    ///
+    /// | Size     | Field                                           |
+    /// |----------|-------------------------------------------------|
+    /// | 0x20     | SHA-256 hash of the image                       |
+    /// | 8        | Base address                                    |
+    /// | 8        | Image size, as mapped                           |
+    /// | 8        | BSS size                                        |
+    /// | 2        | Name length in bytes                            |
+    /// | Variable | Name, UTF-8 and not null-terminated             |
+    ///
+    /// Names longer than `u16::MAX` bytes are truncated.
+    pub fn export_manifest(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MANIFEST_MAGIC);
+        out.extend_from_slice(&MANIFEST_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.program_id.to_le_bytes());
+        out.extend_from_slice(self.set_fingerprint().as_bytes());
+
+        let modules: Vec<_> = self.iter_load_order().collect();
+        out.extend_from_slice(&(modules.len() as u32).to_le_bytes());
+
+        for module in modules {
+            let name = &module.name.as_bytes()[..module.name.len().min(u16::MAX as usize)];
+
+            out.extend_from_slice(module.hash.as_bytes());
+            out.extend_from_slice(&(module.base() as u64).to_le_bytes());
+            out.extend_from_slice(&(module.mapped_size as u64).to_le_bytes());
+            out.extend_from_slice(&(module.bss_size as u64).to_le_bytes());
+            out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            out.extend_from_slice(name);
+        }
+        out
+    }
+
+    /// Writes a plain text report of the mount, for CI logs and support tooling. Modules are sorted
+    /// by name, and base addresses are left out as they change with every boot, so two mounts of the
+    /// same plugins give the same report. For each module one line is written, either
+    ///
+    /// ```text
+    /// <name>: ok hash=<hex> image=<size> bss=<size>
+    /// ```
+    ///
+    /// or `<name>: error: <error>` for modules which failed. These follow a header with the
+    /// program id and [`MountInfo::set_fingerprint`]. Like in [`MountInfo::export_manifest`], the
+    /// BSS size is the one `GetBufferSize` asked for.
+    pub fn write_report<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        self.write_report_inner(w, false)
+    }
+
+    /// Like [`MountInfo::write_report`], with ` base=<address>` added after each module's hash, for
+    /// matching up addresses from a crash in the same boot.
+    pub fn write_report_with_bases<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        self.write_report_inner(w, true)
+    }
+
+    fn write_report_inner<W: Write>(&self, w: &mut W, bases: bool) -> std::io::Result<()> {
+        // `modules` is public and may have been changed since, so don't rely on the lengths matching
+        let names = self.names.iter().map(String::as_str).chain(std::iter::repeat(""));
+        let entries: Vec<_> = names.zip(self.modules.iter())
+            .map(|(name, module)| match module {
+                Ok(module) => (module.name.as_str(), Ok(ReportEntry {
+                    hash: module.hash,
+                    base: bases.then(|| module.base() as usize),
+                    image_size: module.mapped_size,
+                    bss_size: module.bss_size,
+                })),
+                Err(e) => (name, Err(e)),
+            })
+            .collect();
+        write_report(w, self.program_id, &self.set_fingerprint(), entries)
+    }
+}
+
+/// What the report says about a mounted module.
+struct ReportEntry {
+    hash: Sha256Hash,
+    base: Option<usize>,
+    image_size: usize,
+    bss_size: usize,
+}
+
+fn write_report<W: Write>(
+    w: &mut W,
+    program_id: u64,
+    fingerprint: &Sha256Hash,
+    mut entries: Vec<(&str, Result<ReportEntry, &LoaderError>)>
+) -> std::io::Result<()> {
+    writeln!(w, "program id: {:#018x}", program_id)?;
+    writeln!(w, "fingerprint: {}", fingerprint)?;
+
+    entries.sort_by_key(|(name, _)| *name);
+    for (name, entry) in entries {
+        match entry {
+            Ok(entry) => {
+                write!(w, "{}: ok hash={}", name, entry.hash)?;
+                if let Some(base) = entry.base {
+                    write!(w, " base={:#x}", base)?;
+                }
+                writeln!(w, " image={:#x} bss={:#x}", entry.image_size, entry.bss_size)?;
+            },
+            Err(e) => writeln!(w, "{}: error: {}", name, e)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(byte: u8, base: Option<usize>) -> ReportEntry {
+        ReportEntry { hash: Sha256Hash([byte; 0x20]), base, image_size: 0x3000, bss_size: 0x1000 }
+    }
+
+    fn report(entries: Vec<(&str, Result<ReportEntry, &LoaderError>)>) -> String {
+        let mut out = Vec::new();
+        write_report(&mut out, 0x0100_0000_0000_1234, &Sha256Hash([0xff; 0x20]), entries).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn golden() {
+        let error = LoaderError::UnknownModule { name: "broken.nro".to_owned() };
+        let report = report(vec![
+            ("zeta.nro", Ok(entry(0x22, None))),
+            ("broken.nro", Err(&error)),
+            ("alpha.nro", Ok(entry(0x11, None))),
+        ]);
+        let expected = format!(
+            "program id: 0x0100000000001234\n\
+             fingerprint: {}\n\
+             alpha.nro: ok hash={} image=0x3000 bss=0x1000\n\
+             broken.nro: error: No module named broken.nro is mounted\n\
+             zeta.nro: ok hash={} image=0x3000 bss=0x1000\n",
+            "ff".repeat(0x20),
+            "11".repeat(0x20),
+            "22".repeat(0x20),
+        );
+        assert_eq!(report, expected);
+    }
+
+    #[test]
+    fn bases() {
+        let report = report(vec![("plugin.nro", Ok(entry(0x11, Some(0x80_0000_0000))))]);
+        let line = report.lines().nth(2).unwrap();
+        assert_eq!(line, format!("plugin.nro: ok hash={} base=0x8000000000 image=0x3000 bss=0x1000", "11".repeat(0x20)));
+    }
+}
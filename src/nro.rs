@@ -0,0 +1,525 @@
+//! Bounds-checked parsing of NRO images.
+//!
+//! Every offset and size read from the image is validated before it's used, so malformed or
+//! malicious input results in an [`InvalidNroReason`] rather than an out of bounds read.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::LoaderError;
+
+pub const NRO_MAGIC: u32 = 0x304F524E;
+pub const MOD0_MAGIC: u32 = 0x30444F4D;
+pub const HEADER_SIZE: usize = 0x80;
+const MOD0_SIZE: usize = 0x1C;
+
+/// Why an image was rejected.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InvalidNroReason {
+    /// The file is empty, e.g. an editor temp file or an interrupted download.
+    Empty,
+    /// The image is smaller than an NRO header.
+    TooSmall { len: usize },
+    /// The header doesn't start with `NRO0`.
+    BadMagic,
+    /// The header declares more bytes than the image contains.
+    Truncated { declared: usize, actual: usize },
+    /// A segment extends past the end of the image.
+    SegmentOutOfBounds { segment: &'static str },
+    /// The MOD0 offset points outside of the image.
+    Mod0OutOfBounds { offset: usize },
+    /// A MOD0 relative offset points outside of the image.
+    Mod0FieldOutOfBounds { field: &'static str },
+}
+
+impl fmt::Display for InvalidNroReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "file is empty"),
+            Self::TooSmall { len } => write!(f, "image is only {:#x} bytes", len),
+            Self::BadMagic => write!(f, "missing NRO0 magic"),
+            Self::Truncated { declared, actual } => write!(f, "header declares {:#x} bytes but the image is {:#x}", declared, actual),
+            Self::SegmentOutOfBounds { segment } => write!(f, "{} segment is out of bounds", segment),
+            Self::Mod0OutOfBounds { offset } => write!(f, "MOD0 offset {:#x} is out of bounds", offset),
+            Self::Mod0FieldOutOfBounds { field } => write!(f, "MOD0 {} offset is out of bounds", field),
+        }
+    }
+}
+
+fn invalid(reason: InvalidNroReason) -> LoaderError {
+    LoaderError::InvalidNro { reason }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// A segment of the image, as an offset from the start of the image and a size.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// The MOD0 header. The offsets here have already been resolved relative to the start of the
+/// image, and are `None` when the field is zero (i.e. points at the MOD0 header itself).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Mod0 {
+    pub offset: usize,
+    pub dynamic: Option<usize>,
+    pub bss_start: usize,
+    pub bss_end: usize,
+    pub eh_frame_hdr_start: usize,
+    pub eh_frame_hdr_end: usize,
+    pub module_object: usize,
+}
+
+/// A validated view over an NRO image.
+pub struct NroView<'a> {
+    data: &'a [u8],
+    pub size: usize,
+    pub text: Segment,
+    pub ro: Segment,
+    pub data_segment: Segment,
+    pub bss_size: usize,
+    pub module_id: [u8; 0x20],
+    /// The MOD0 header, if the image has one.
+    pub mod0: Option<Mod0>,
+}
+
+/// Whether `data` starts with an NRO header, without validating anything else.
+pub fn has_magic(data: &[u8]) -> bool {
+    read_u32(data, 0x10) == Some(NRO_MAGIC)
+}
+
+/// The image size the header declares, without validating anything else. `None` if `data` is too
+/// short to contain it.
+pub fn declared_size(data: &[u8]) -> Option<usize> {
+    read_u32(data, 0x18).map(|size| size as usize)
+}
+
+/// Validates `data` as an NRO image.
+pub fn parse(data: &[u8]) -> Result<NroView<'_>, LoaderError> {
+    if data.is_empty() {
+        return Err(invalid(InvalidNroReason::Empty));
+    }
+    if data.len() < HEADER_SIZE {
+        return Err(invalid(InvalidNroReason::TooSmall { len: data.len() }));
+    }
+
+    // Every header field is within HEADER_SIZE, so these reads can't fail from here on
+    let field = |offset| read_u32(data, offset).unwrap() as usize;
+
+    if field(0x10) as u32 != NRO_MAGIC {
+        return Err(invalid(InvalidNroReason::BadMagic));
+    }
+
+    let size = field(0x18);
+    if size > data.len() {
+        return Err(invalid(InvalidNroReason::Truncated { declared: size, actual: data.len() }));
+    }
+
+    let segment = |index: usize, name| {
+        let segment = Segment { offset: field(0x20 + index * 8), size: field(0x24 + index * 8) };
+        match segment.offset.checked_add(segment.size) {
+            Some(end) if end <= size => Ok(segment),
+            _ => Err(invalid(InvalidNroReason::SegmentOutOfBounds { segment: name })),
+        }
+    };
+
+    let text = segment(0, "text")?;
+    let ro = segment(1, "ro")?;
+    let data_segment = segment(2, "data")?;
+
+    let mut module_id = [0; 0x20];
+    module_id.copy_from_slice(&data[0x40..0x60]);
+
+    Ok(NroView {
+        data,
+        size,
+        text,
+        ro,
+        data_segment,
+        bss_size: field(0x38),
+        module_id,
+        mod0: parse_mod0(data, field(0x4), size.saturating_add(field(0x38)))?,
+    })
+}
+
+/// `mapped_end` is where the BSS ends once the image is mapped, which the BSS and module object
+/// fields are allowed to point up to.
+fn parse_mod0(data: &[u8], offset: usize, mapped_end: usize) -> Result<Option<Mod0>, LoaderError> {
+    match offset.checked_add(MOD0_SIZE) {
+        Some(end) if end <= data.len() => {},
+        _ => return Err(invalid(InvalidNroReason::Mod0OutOfBounds { offset })),
+    }
+
+    if read_u32(data, offset) != Some(MOD0_MAGIC) {
+        return Ok(None);
+    }
+
+    // Ends are inclusive since fields like `bss_end` point one past the range they describe
+    let relative = |index: usize, name, end: usize| {
+        let value = read_u32(data, offset + index * 4).unwrap() as i32;
+        offset.checked_add_signed(value as isize)
+            .filter(|&resolved| resolved <= end)
+            .ok_or_else(|| invalid(InvalidNroReason::Mod0FieldOutOfBounds { field: name }))
+    };
+
+    let dynamic = relative(1, "dynamic", data.len())?;
+    Ok(Some(Mod0 {
+        offset,
+        dynamic: (dynamic != offset).then_some(dynamic),
+        bss_start: relative(2, "bss_start", mapped_end)?,
+        bss_end: relative(3, "bss_end", mapped_end)?,
+        eh_frame_hdr_start: relative(4, "eh_frame_hdr_start", data.len())?,
+        eh_frame_hdr_end: relative(5, "eh_frame_hdr_end", data.len())?,
+        module_object: relative(6, "module_object", mapped_end)?,
+    }))
+}
+
+impl<'a> NroView<'a> {
+    /// The whole image.
+    pub fn bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Reads the `(tag, value)` pairs of the dynamic section up to `DT_NULL`. Entries which would
+    /// run past the end of the image are dropped.
+    pub fn dynamic_entries(&self) -> Vec<(u64, u64)> {
+        let Some(mut offset) = self.mod0.and_then(|mod0| mod0.dynamic) else { return Vec::new() };
+
+        let mut entries = Vec::new();
+        while let (Some(tag), Some(value)) = (read_u64(self.data, offset), read_u64(self.data, offset + 8)) {
+            if tag == 0 {
+                break;
+            }
+            entries.push((tag, value));
+            offset += 0x10;
+        }
+        entries
+    }
+
+    /// The number of relocations in the `DT_RELA` and `DT_JMPREL` tables, i.e. how many
+    /// `LoadModule` has to apply. `None` if the image has no dynamic section.
+    pub fn relocation_count(&self) -> Option<usize> {
+        let entries = self.dynamic_entries();
+        if entries.is_empty() {
+            return None;
+        }
+        let value = |tag| entries.iter().find(|(entry, _)| *entry == tag).map(|(_, value)| *value);
+
+        let rela_size = value(DT_RELAENT).filter(|size| *size != 0).unwrap_or(RELA_SIZE);
+        let rela = value(DT_RELASZ).unwrap_or(0) / rela_size;
+        let plt_size = if value(DT_PLTREL) == Some(DT_REL) { REL_SIZE } else { RELA_SIZE };
+        let plt = value(DT_PLTRELSZ).unwrap_or(0) / plt_size;
+        usize::try_from(rela.saturating_add(plt)).ok()
+    }
+
+    /// The `.eh_frame_hdr` section, as offsets into the image. `None` if the image has no MOD0 or
+    /// the range is empty.
+    pub fn eh_frame_hdr_range(&self) -> Option<Range<usize>> {
+        let mod0 = self.mod0?;
+        (mod0.eh_frame_hdr_start < mod0.eh_frame_hdr_end).then_some(mod0.eh_frame_hdr_start..mod0.eh_frame_hdr_end)
+    }
+
+    /// The `.eh_frame` section, as offsets into the image. The start comes from the pointer in
+    /// `.eh_frame_hdr`, and the end is found by walking the CIE/FDE records up to the zero
+    /// terminator, which is included. Returns `None` if the header uses a pointer encoding other
+    /// than an absolute, pc-relative or data-relative fixed size one.
+    pub fn eh_frame_range(&self) -> Option<Range<usize>> {
+        let hdr = self.eh_frame_hdr_range()?;
+        if *self.data.get(hdr.start)? != 1 {
+            return None;
+        }
+
+        let start = read_encoded_pointer(self.data, hdr.start + 4, *self.data.get(hdr.start + 1)?, hdr.start)?;
+
+        let mut end = start;
+        while let Some(length) = read_u32(self.data, end) {
+            let next = match length {
+                0 => end + 4,
+                0xFFFF_FFFF => read_u64(self.data, end + 4)
+                    .and_then(|length| usize::try_from(length).ok())
+                    .and_then(|length| (end + 12).checked_add(length))?,
+                _ => end + 4 + length as usize,
+            };
+            if next > self.data.len() {
+                break;
+            }
+            end = next;
+            if length == 0 {
+                break;
+            }
+        }
+        Some(start..end)
+    }
+
+    /// Finds the first valid [`PluginMeta`] record in the read-only segment.
+    pub fn plugin_meta(&self) -> Option<PluginMeta> {
+        let ro = &self.data[self.ro.offset..self.ro.offset + self.ro.size];
+        (0..ro.len().saturating_sub(PLUGIN_META_MAGIC.len()))
+            .step_by(4)
+            .filter(|&offset| ro[offset..].starts_with(PLUGIN_META_MAGIC))
+            .find_map(|offset| read_plugin_meta(ro, offset + PLUGIN_META_MAGIC.len()))
+    }
+
+    /// The GNU build id the linker wrote to the `.note.gnu.build-id` note, usually 20 bytes. NROs
+    /// have no section headers, so the read-only segment is scanned for the note instead.
+    pub fn build_id(&self) -> Option<&'a [u8]> {
+        let ro = &self.data[self.ro.offset..self.ro.offset + self.ro.size];
+        (0..ro.len().saturating_sub(GNU_NOTE_HEADER_SIZE))
+            .step_by(4)
+            .find_map(|offset| read_build_id(ro, offset))
+    }
+}
+
+// An ELF note header (name size, descriptor size, type) followed by the "GNU" name
+const GNU_NOTE_HEADER_SIZE: usize = 0x10;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+fn read_build_id(data: &[u8], offset: usize) -> Option<&[u8]> {
+    let (name_size, id_size, kind) = (read_u32(data, offset)?, read_u32(data, offset + 4)?, read_u32(data, offset + 8)?);
+    if name_size != 4 || kind != NT_GNU_BUILD_ID || data.get(offset + 12..offset + 16)? != b"GNU\0" {
+        return None;
+    }
+    // Linkers write 8 to 20 bytes depending on the style, and explicit `--build-id=0x...` ids are
+    // rarely longer than this, so anything else is a false match
+    if !(8..=0x40).contains(&id_size) {
+        return None;
+    }
+    let start = offset + GNU_NOTE_HEADER_SIZE;
+    data.get(start..start + id_size as usize)
+}
+
+fn read_plugin_meta(data: &[u8], offset: usize) -> Option<PluginMeta> {
+    let flags = read_u64(data, offset)?;
+    let lengths = read_u32(data, offset + 8)?;
+    let (name_length, version_length) = ((lengths & 0xFFFF) as usize, (lengths >> 16) as usize);
+
+    let name = data.get(offset + 12..offset + 12 + name_length)?;
+    let version = data.get(offset + 12 + name_length..offset + 12 + name_length + version_length)?;
+    Some(PluginMeta {
+        name: std::str::from_utf8(name).ok()?.to_string(),
+        version: std::str::from_utf8(version).ok()?.to_string(),
+        flags,
+    })
+}
+
+/// Marks the start of an embedded [`PluginMeta`] record.
+pub const PLUGIN_META_MAGIC: &[u8; 8] = b"LDRMETA0";
+
+/// Metadata a plugin can embed to describe itself, read by [`NroView::plugin_meta`].
+///
+/// The record lives anywhere in the read-only segment, 4-byte aligned, laid out as (little-endian):
+///
+/// | Size     | Field                          |
+/// |----------|--------------------------------|
+/// | 8        | [`PLUGIN_META_MAGIC`]          |
+/// | 8        | Flags                          |
+/// | 2        | Name length in bytes           |
+/// | 2        | Version length in bytes        |
+/// | Variable | Name, UTF-8                    |
+/// | Variable | Version, UTF-8                 |
+///
+/// A plugin embeds it as a `#[used]` static holding those bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginMeta {
+    pub name: String,
+    pub version: String,
+    /// Meaning is up to the plugins and the host.
+    pub flags: u64,
+}
+
+// Dynamic section tags, and the sizes of the relocation entries they describe
+const DT_PLTRELSZ: u64 = 2;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_REL: u64 = 17;
+const DT_PLTREL: u64 = 20;
+const RELA_SIZE: u64 = 0x18;
+const REL_SIZE: u64 = 0x10;
+
+// DWARF exception header pointer encodings. The low nibble is the format, the high nibble what the
+// value is relative to.
+const DW_EH_PE_ABSPTR: u8 = 0x00;
+const DW_EH_PE_UDATA4: u8 = 0x03;
+const DW_EH_PE_UDATA8: u8 = 0x04;
+const DW_EH_PE_SDATA4: u8 = 0x0B;
+const DW_EH_PE_SDATA8: u8 = 0x0C;
+const DW_EH_PE_PCREL: u8 = 0x10;
+const DW_EH_PE_DATAREL: u8 = 0x30;
+
+/// Decodes the pointer at `offset`, returning it as an offset into the image. Data-relative values
+/// are relative to `data_base`.
+fn read_encoded_pointer(data: &[u8], offset: usize, encoding: u8, data_base: usize) -> Option<usize> {
+    let value = match encoding & 0x0F {
+        DW_EH_PE_ABSPTR | DW_EH_PE_UDATA8 | DW_EH_PE_SDATA8 => read_u64(data, offset)? as i64,
+        DW_EH_PE_UDATA4 => read_u32(data, offset)? as i64,
+        DW_EH_PE_SDATA4 => read_u32(data, offset)? as i32 as i64,
+        _ => return None,
+    };
+
+    let base = match encoding & 0x70 {
+        0 => 0,
+        DW_EH_PE_PCREL => offset,
+        DW_EH_PE_DATAREL => data_base,
+        _ => return None,
+    };
+
+    base.checked_add_signed(isize::try_from(value).ok()?)
+        .filter(|&pointer| pointer < data.len())
+}
+
+/// Works around modules whose MOD0 places the module object at the very end of the BSS, without
+/// reserving space for it, by growing the BSS by the size of the module object.
+pub fn fix_bss_size(data: &mut [u8]) -> Result<(), LoaderError> {
+    if let Some((offset, bss_end)) = bss_fixup(data)? {
+        data[offset..offset + 4].copy_from_slice(&bss_end.to_le_bytes());
+    }
+    Ok(())
+}
+
+/// Where [`fix_bss_size`] writes the new BSS end and what it writes, or `None` if the module
+/// doesn't need fixing.
+fn bss_fixup(data: &[u8]) -> Result<Option<(usize, u32)>, LoaderError> {
+    // These offsets are relative to 0x18 bytes past the MOD0 offset
+    let base = read_u32(data, 4)
+        .and_then(|offset| (offset as usize).checked_add(0x18))
+        .ok_or(invalid(InvalidNroReason::TooSmall { len: data.len() }))?;
+    let out_of_bounds = || invalid(InvalidNroReason::Mod0OutOfBounds { offset: base });
+
+    let bss_end_offset = base + 3 * 4;
+    let bss_end = read_u32(data, bss_end_offset).ok_or_else(out_of_bounds)?;
+    let module_object = read_u32(data, base + 7 * 4).ok_or_else(out_of_bounds)?;
+    Ok((bss_end == module_object).then_some((bss_end_offset, bss_end.wrapping_add(0xD0))))
+}
+
+/// Checks everything about `data` that the loader checks before hashing and mounting a plugin:
+/// the header, magic, sizes, segment bounds and MOD0. Nothing is modified and `nn::ro` isn't
+/// involved, so this works off the console too. `nn::ro` can still reject an image which passes,
+/// e.g. because of its hash or something wrong in the segments themselves.
+pub fn validate(data: &[u8]) -> Result<(), LoaderError> {
+    parse(data)?;
+    bss_fixup(data).map(drop)
+}
+
+/// Runs `data` through everything which reads from an untrusted image. Used by the fuzz targets,
+/// which only check that this never panics.
+#[cfg(feature = "fuzzing")]
+pub fn fuzz(data: &[u8]) {
+    let _ = validate(data);
+    if let Ok(view) = parse(data) {
+        let _ = view.dynamic_entries();
+        let _ = view.eh_frame_range();
+        let _ = view.plugin_meta();
+    }
+
+    let mut data = data.to_vec();
+    if fix_bss_size(&mut data).is_ok() {
+        let _ = parse(&data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 0x1000;
+    const MOD0_OFFSET: usize = 0x80;
+
+    fn put(image: &mut [u8], offset: usize, value: u32) {
+        image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// A page with a header whose segments cover the whole image, and a MOD0 whose offsets all
+    /// point at itself.
+    fn image() -> Vec<u8> {
+        let mut image = vec![0; SIZE];
+        put(&mut image, 0x4, MOD0_OFFSET as u32);
+        put(&mut image, 0x10, NRO_MAGIC);
+        put(&mut image, 0x18, SIZE as u32);
+        put(&mut image, 0x24, SIZE as u32);
+        put(&mut image, MOD0_OFFSET, MOD0_MAGIC);
+        image
+    }
+
+    fn reason(data: &[u8]) -> InvalidNroReason {
+        match validate(data) {
+            Err(LoaderError::InvalidNro { reason }) => reason,
+            other => panic!("expected an invalid NRO, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn valid() {
+        let image = image();
+        assert!(validate(&image).is_ok());
+        let view = parse(&image).unwrap();
+        assert_eq!(view.text, Segment { offset: 0, size: SIZE });
+        assert_eq!(view.mod0.map(|mod0| mod0.offset), Some(MOD0_OFFSET));
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(reason(&[]), InvalidNroReason::Empty);
+    }
+
+    #[test]
+    fn too_small() {
+        assert_eq!(reason(&image()[..0x40]), InvalidNroReason::TooSmall { len: 0x40 });
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut image = image();
+        image[0x10] = 0;
+        assert_eq!(reason(&image), InvalidNroReason::BadMagic);
+    }
+
+    #[test]
+    fn truncated() {
+        assert_eq!(reason(&image()[..0x800]), InvalidNroReason::Truncated { declared: SIZE, actual: 0x800 });
+    }
+
+    #[test]
+    fn segment_out_of_bounds() {
+        let mut image = image();
+        put(&mut image, 0x28, 0x800);
+        put(&mut image, 0x2C, 0x801);
+        assert_eq!(reason(&image), InvalidNroReason::SegmentOutOfBounds { segment: "ro" });
+
+        // Offset and size which overflow when added
+        put(&mut image, 0x28, u32::MAX);
+        put(&mut image, 0x2C, u32::MAX);
+        assert_eq!(reason(&image), InvalidNroReason::SegmentOutOfBounds { segment: "ro" });
+    }
+
+    #[test]
+    fn mod0_near_u32_max() {
+        let mut image = image();
+        put(&mut image, 0x4, u32::MAX - 4);
+        assert_eq!(reason(&image), InvalidNroReason::Mod0OutOfBounds { offset: u32::MAX as usize - 4 });
+    }
+
+    #[test]
+    fn mod0_field_out_of_bounds() {
+        let mut image = image();
+        put(&mut image, MOD0_OFFSET + 0x4, SIZE as u32);
+        assert_eq!(reason(&image), InvalidNroReason::Mod0FieldOutOfBounds { field: "dynamic" });
+    }
+
+    #[test]
+    fn missing_mod0_is_allowed() {
+        let mut image = image();
+        put(&mut image, MOD0_OFFSET, 0);
+        assert!(validate(&image).is_ok());
+        assert!(parse(&image).unwrap().mod0.is_none());
+    }
+}
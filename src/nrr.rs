@@ -0,0 +1,289 @@
+//! Building NRR images.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::mem::offset_of;
+
+use crate::nn::ro::NrrHeader;
+use crate::{LoaderError, Sha256Hash, NRR_HASHES_OFFSET};
+
+pub const NRR_MAGIC: u32 = 0x3052524E;
+
+// Offsets of the fields written by `NrrBuilder`, matching `nn::ro::NrrHeader`. Like the header's
+// size (see `NRR_HASHES_OFFSET`), they're checked against the bindings at compile time.
+const MAGIC_OFFSET: usize = 0x0;
+const PROGRAM_ID_OFFSET: usize = 0x330;
+pub(crate) const SIZE_OFFSET: usize = 0x338;
+const KIND_OFFSET: usize = 0x33C;
+const HASHES_OFFSET_OFFSET: usize = 0x340;
+const NUM_HASHES_OFFSET: usize = 0x344;
+
+const _: () = assert!(offset_of!(NrrHeader, magic) == MAGIC_OFFSET);
+const _: () = assert!(offset_of!(NrrHeader, program_id) == PROGRAM_ID_OFFSET);
+const _: () = assert!(offset_of!(NrrHeader, size) == SIZE_OFFSET);
+const _: () = assert!(offset_of!(NrrHeader, type_) == KIND_OFFSET);
+const _: () = assert!(offset_of!(NrrHeader, hashes_offset) == HASHES_OFFSET_OFFSET);
+const _: () = assert!(offset_of!(NrrHeader, num_hashes) == NUM_HASHES_OFFSET);
+
+/// What an NRR is allowed to authorize.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum NrrKind {
+    /// Regular modules, loaded with `nn::ro::LoadModule`.
+    #[default]
+    User = 0,
+    /// JIT plugins, accepted from firmware 7.0.0 onwards.
+    JitPlugin = 1,
+}
+
+/// The first firmware version (major, minor, micro) to accept each kind of NRR. Kinds missing
+/// from here are assumed to work everywhere.
+const KIND_SUPPORT: &[(NrrKind, (u8, u8, u8))] = &[
+    (NrrKind::JitPlugin, (7, 0, 0)),
+];
+
+impl NrrKind {
+    /// The oldest firmware which accepts this kind, as (major, minor, micro).
+    pub fn min_firmware(self) -> (u8, u8, u8) {
+        KIND_SUPPORT.iter()
+            .find(|(kind, _)| *kind == self)
+            .map_or((0, 0, 0), |(_, version)| *version)
+    }
+}
+
+/// Lays out an NRR image: the header, followed by the hashes, padded to a page.
+///
+/// Nothing here touches `nn::ro`, see [`crate::register_nrr`] for registering the result.
+#[derive(Clone, Debug)]
+pub struct NrrBuilder {
+    program_id: u64,
+    kind: NrrKind,
+    sort: bool,
+    hashes: Vec<Sha256Hash>,
+}
+
+impl NrrBuilder {
+    pub fn new(program_id: u64) -> Self {
+        Self { program_id, kind: NrrKind::default(), sort: true, hashes: Vec::new() }
+    }
+
+    pub fn program_id(&self) -> u64 {
+        self.program_id
+    }
+
+    pub fn kind(mut self, kind: NrrKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// The kind set with [`NrrBuilder::kind`].
+    pub fn nrr_kind(&self) -> NrrKind {
+        self.kind
+    }
+
+    /// Sets the hashes to write. They're sorted unless [`NrrBuilder::sort`] is turned off.
+    pub fn hashes(mut self, hashes: &[Sha256Hash]) -> Self {
+        self.hashes = hashes.to_vec();
+        self
+    }
+
+    /// Whether to sort the hashes before writing them, on by default. Current firmware binary
+    /// searches the hash table, so an unsorted NRR registers fine but then rejects most of its
+    /// modules. Only turn this off for SDKs which are known to expect insertion order.
+    pub fn sort(mut self, sort: bool) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// The hashes in the order they are written to the image.
+    pub fn ordered_hashes(&self) -> Vec<Sha256Hash> {
+        let mut hashes = self.hashes.clone();
+        if self.sort {
+            hashes.sort();
+        }
+        hashes
+    }
+
+    /// The size of the image, which is always a multiple of 0x1000.
+    pub fn image_size(&self) -> usize {
+        align_up!(NRR_HASHES_OFFSET + std::mem::size_of_val(self.hashes.as_slice()), 0x1000)
+    }
+
+    /// Writes the image to `out`, which has to be exactly [`NrrBuilder::image_size`] bytes. Every
+    /// byte is written, so `out` doesn't need to be zeroed first.
+    pub fn write(&self, out: &mut [u8]) {
+        assert_eq!(out.len(), self.image_size(), "NRR buffer has the wrong size");
+        out.fill(0);
+
+        let mut put = |offset: usize, bytes: &[u8]| out[offset..offset + bytes.len()].copy_from_slice(bytes);
+        put(MAGIC_OFFSET, &NRR_MAGIC.to_le_bytes());
+        put(PROGRAM_ID_OFFSET, &self.program_id.to_le_bytes());
+        put(SIZE_OFFSET, &(self.image_size() as u32).to_le_bytes());
+        put(KIND_OFFSET, &[self.kind as u8]);
+        put(HASHES_OFFSET_OFFSET, &(NRR_HASHES_OFFSET as u32).to_le_bytes());
+        put(NUM_HASHES_OFFSET, &(self.hashes.len() as u32).to_le_bytes());
+
+        for (index, hash) in self.ordered_hashes().iter().enumerate() {
+            put(NRR_HASHES_OFFSET + index * 0x20, hash.as_bytes());
+        }
+    }
+
+    /// Builds the image into a new buffer. `nn::ro` needs the image page-aligned, which a `Vec`
+    /// isn't, so use [`NrrBuilder::write`] to build one for registration.
+    pub fn build(&self) -> Vec<u8> {
+        let mut image = vec![0; self.image_size()];
+        self.write(&mut image);
+        image
+    }
+}
+
+/// Builds an NRR image with `hashes` in exactly the order given, so `hashes[i]` ends up in slot
+/// `i` of the hash table.
+///
+/// This is an escape hatch for debugging SDKs which depend on the order of the table; on current
+/// firmware an unsorted NRR rejects most of its modules (see [`NrrBuilder::sort`]). To get the same
+/// layout when mounting, combine [`crate::MountOptions::sort_hashes`]`(false)` with
+/// [`crate::LoadOrder::Explicit`], then [`crate::MountInfo::nrr_position`] maps each module to its
+/// slot.
+pub fn build_nrr_ordered(program_id: u64, hashes: &[Sha256Hash]) -> Vec<u8> {
+    NrrBuilder::new(program_id).hashes(hashes).sort(false).build()
+}
+
+/// Why an NRR image was rejected by [`verify_nrr`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InvalidNrrReason {
+    /// The image is smaller than an NRR header.
+    TooSmall { len: usize },
+    /// The header doesn't start with `NRR0`.
+    BadMagic,
+    /// The hash table extends past the end of the image.
+    HashesOutOfBounds,
+}
+
+impl fmt::Display for InvalidNrrReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooSmall { len } => write!(f, "image is only {:#x} bytes", len),
+            Self::BadMagic => write!(f, "missing NRR0 magic"),
+            Self::HashesOutOfBounds => write!(f, "hash table is out of bounds"),
+        }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reads the hash table out of an NRR image, checking the header along the way.
+pub fn read_hashes(nrr: &[u8]) -> Result<Vec<Sha256Hash>, LoaderError> {
+    let invalid = |reason| LoaderError::InvalidNrr { reason };
+
+    if nrr.len() < NRR_HASHES_OFFSET {
+        return Err(invalid(InvalidNrrReason::TooSmall { len: nrr.len() }));
+    }
+    if read_u32(nrr, MAGIC_OFFSET) != NRR_MAGIC {
+        return Err(invalid(InvalidNrrReason::BadMagic));
+    }
+
+    let offset = read_u32(nrr, HASHES_OFFSET_OFFSET) as usize;
+    let count = read_u32(nrr, NUM_HASHES_OFFSET) as usize;
+    let table = count.checked_mul(0x20)
+        .and_then(|size| offset.checked_add(size))
+        .and_then(|end| nrr.get(offset..end))
+        .ok_or(invalid(InvalidNrrReason::HashesOutOfBounds))?;
+
+    Ok(table.chunks_exact(0x20).map(|hash| Sha256Hash(hash.try_into().unwrap())).collect())
+}
+
+/// Checks that the hashes in `nrr` are exactly the hashes of `nros`, ignoring order.
+///
+/// This doesn't touch `nn::ro`, but hashing goes through `nn::crypto` like everywhere else, so it
+/// still needs the SDK to link.
+pub fn verify_nrr(nrr: &[u8], nros: &[&[u8]]) -> Result<(), LoaderError> {
+    let registered: BTreeSet<Sha256Hash> = read_hashes(nrr)?.into_iter().collect();
+    let expected: BTreeSet<Sha256Hash> = nros.iter().map(|nro| Sha256Hash::new(nro)).collect();
+
+    if registered == expected {
+        return Ok(());
+    }
+    Err(LoaderError::NrrHashMismatch {
+        missing: expected.difference(&registered).copied().collect(),
+        extra: registered.difference(&expected).copied().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(byte: u8) -> Sha256Hash {
+        Sha256Hash([byte; 0x20])
+    }
+
+    #[test]
+    fn header_layout() {
+        let image = NrrBuilder::new(0x0100_0000_0000_1234).kind(NrrKind::JitPlugin).hashes(&[hash(1)]).build();
+
+        assert_eq!(image.len(), 0x1000);
+        assert_eq!(&image[MAGIC_OFFSET..MAGIC_OFFSET + 4], b"NRR0");
+        assert_eq!(image[PROGRAM_ID_OFFSET..PROGRAM_ID_OFFSET + 8], 0x0100_0000_0000_1234u64.to_le_bytes());
+        assert_eq!(read_u32(&image, SIZE_OFFSET), 0x1000);
+        assert_eq!(image[KIND_OFFSET], 1);
+        assert_eq!(read_u32(&image, HASHES_OFFSET_OFFSET) as usize, NRR_HASHES_OFFSET);
+        assert_eq!(read_u32(&image, NUM_HASHES_OFFSET), 1);
+        assert_eq!(&image[NRR_HASHES_OFFSET..NRR_HASHES_OFFSET + 0x20], hash(1).as_bytes());
+    }
+
+    #[test]
+    fn hashes_are_sorted() {
+        let image = NrrBuilder::new(0).hashes(&[hash(3), hash(1), hash(2)]).build();
+        assert_eq!(read_hashes(&image).unwrap(), [hash(1), hash(2), hash(3)]);
+    }
+
+    #[test]
+    fn unsorted_keeps_order() {
+        let image = build_nrr_ordered(0, &[hash(3), hash(1), hash(2)]);
+        assert_eq!(read_hashes(&image).unwrap(), [hash(3), hash(1), hash(2)]);
+    }
+
+    #[test]
+    fn image_size_is_page_aligned() {
+        let fits = (0x1000 - NRR_HASHES_OFFSET) / 0x20;
+        let hashes: Vec<_> = (0..=fits as u8).map(hash).collect();
+
+        assert_eq!(NrrBuilder::new(0).build().len(), 0x1000);
+        assert_eq!(NrrBuilder::new(0).hashes(&hashes[..fits]).build().len(), 0x1000);
+        assert_eq!(NrrBuilder::new(0).hashes(&hashes).build().len(), 0x2000);
+    }
+
+    #[test]
+    fn write_overwrites_everything() {
+        let builder = NrrBuilder::new(0).hashes(&[hash(1)]);
+        let mut image = vec![0xFF; builder.image_size()];
+        builder.write(&mut image);
+        assert_eq!(image, builder.build());
+    }
+
+    #[test]
+    fn read_hashes_rejects_bad_images() {
+        let mut image = NrrBuilder::new(0).hashes(&[hash(1)]).build();
+        assert!(matches!(
+            read_hashes(&image[..0x100]),
+            Err(LoaderError::InvalidNrr { reason: InvalidNrrReason::TooSmall { len: 0x100 } })
+        ));
+
+        image[NUM_HASHES_OFFSET..NUM_HASHES_OFFSET + 4].copy_from_slice(&0x1000u32.to_le_bytes());
+        assert!(matches!(read_hashes(&image), Err(LoaderError::InvalidNrr { reason: InvalidNrrReason::HashesOutOfBounds })));
+
+        image[MAGIC_OFFSET] = 0;
+        assert!(matches!(read_hashes(&image), Err(LoaderError::InvalidNrr { reason: InvalidNrrReason::BadMagic })));
+    }
+
+    #[test]
+    fn kind_support() {
+        assert_eq!(NrrKind::User.min_firmware(), (0, 0, 0));
+        assert_eq!(NrrKind::JitPlugin.min_firmware(), (7, 0, 0));
+        assert_eq!(NrrBuilder::new(0).nrr_kind(), NrrKind::User);
+        assert_eq!(NrrBuilder::new(0).kind(NrrKind::JitPlugin).nrr_kind(), NrrKind::JitPlugin);
+    }
+}
@@ -0,0 +1,83 @@
+//! Result codes returned by `nn::ro`.
+//!
+//! Result codes pack a module number into the low 9 bits and a description into the rest. Every
+//! code here belongs to the `ro` module (22). Add new entries to [`KNOWN`] as they're identified.
+
+use std::fmt;
+
+const fn ro_result(description: u32) -> u32 {
+    22 | (description << 9)
+}
+
+pub const OUT_OF_ADDRESS_SPACE: u32 = ro_result(2);
+pub const ALREADY_LOADED: u32 = ro_result(3);
+pub const INVALID_NRO: u32 = ro_result(4);
+pub const INVALID_NRR: u32 = ro_result(6);
+pub const TOO_MANY_NRO: u32 = ro_result(7);
+pub const TOO_MANY_NRR: u32 = ro_result(8);
+pub const NOT_AUTHORIZED: u32 = ro_result(9);
+pub const INVALID_NRR_KIND: u32 = ro_result(10);
+pub const INTERNAL_ERROR: u32 = ro_result(1023);
+pub const INVALID_ADDRESS: u32 = ro_result(1025);
+pub const INVALID_SIZE: u32 = ro_result(1026);
+pub const NOT_LOADED: u32 = ro_result(1028);
+pub const NOT_REGISTERED: u32 = ro_result(1029);
+pub const INVALID_SESSION: u32 = ro_result(1030);
+pub const INVALID_PROCESS: u32 = ro_result(1031);
+
+const KNOWN: &[(u32, &str)] = &[
+    (OUT_OF_ADDRESS_SPACE, "Out of address space"),
+    (ALREADY_LOADED, "Module already loaded"),
+    (INVALID_NRO, "Invalid NRO"),
+    (INVALID_NRR, "Invalid NRR"),
+    (TOO_MANY_NRO, "Too many modules loaded"),
+    (TOO_MANY_NRR, "Too many NRRs registered"),
+    (NOT_AUTHORIZED, "Module hash not authorized by any registered NRR"),
+    (INVALID_NRR_KIND, "Invalid NRR kind"),
+    (INTERNAL_ERROR, "Internal error"),
+    (INVALID_ADDRESS, "Invalid address"),
+    (INVALID_SIZE, "Invalid size"),
+    (NOT_LOADED, "Module not loaded"),
+    (NOT_REGISTERED, "NRR not registered"),
+    (INVALID_SESSION, "Invalid session"),
+    (INVALID_PROCESS, "Invalid process"),
+];
+
+/// Returns a description of `rc` if it's a known `nn::ro` result code.
+pub fn describe(rc: u32) -> Option<&'static str> {
+    KNOWN.iter().find(|(code, _)| *code == rc).map(|(_, description)| *description)
+}
+
+/// Displays a result code with its description, e.g. `Out of address space (0x416)`, or just the
+/// hex value if the code isn't known.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ResultCode(pub u32);
+
+impl fmt::Display for ResultCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match describe(self.0) {
+            Some(description) => write!(f, "{} ({:#x})", description, self.0),
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        assert_eq!(OUT_OF_ADDRESS_SPACE, 0x416);
+        assert_eq!(INVALID_ADDRESS, 0x80216);
+        assert_eq!(INVALID_SIZE, 0x80416);
+        assert_eq!(NOT_LOADED, 0x80816);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(ResultCode(OUT_OF_ADDRESS_SPACE).to_string(), "Out of address space (0x416)");
+        assert_eq!(ResultCode(INVALID_SIZE).to_string(), "Invalid size (0x80416)");
+        assert_eq!(ResultCode(ro_result(1027)).to_string(), "0x80616");
+    }
+}
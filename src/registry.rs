@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+
+use crate::Sha256Hash;
+
+/// The hashes registered by every mount sharing this registry, see [`crate::MountOptions::registry`].
+///
+/// Hashes are added once their NRR is registered, and removed again when it's unregistered. A hash
+/// in more than one NRR stays in the registry until the last of them is unregistered.
+#[derive(Default)]
+pub struct Registry {
+    // How many registered NRRs hold each hash
+    hashes: Mutex<HashMap<Sha256Hash, usize>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `hash` is in an NRR registered by one of the mounts sharing this registry.
+    pub fn contains(&self, hash: &Sha256Hash) -> bool {
+        self.lock().contains_key(hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    pub(crate) fn insert(&self, hashes: &[Sha256Hash]) {
+        let mut registered = self.lock();
+        for hash in hashes {
+            *registered.entry(*hash).or_default() += 1;
+        }
+    }
+
+    pub(crate) fn remove(&self, hashes: &[Sha256Hash]) {
+        let mut registered = self.lock();
+        for hash in hashes {
+            if let Some(count) = registered.get_mut(hash) {
+                *count -= 1;
+                if *count == 0 {
+                    registered.remove(hash);
+                }
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Sha256Hash, usize>> {
+        self.hashes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::tests::{nro_image, temp_dir};
+    use crate::{mount_from_directory_with_options, ro, Diagnostic, LoadOrder, MountOptions, SkipReason};
+
+    fn hash(byte: u8) -> Sha256Hash {
+        Sha256Hash([byte; 0x20])
+    }
+
+    #[test]
+    fn overlapping_sets() {
+        let registry = Registry::new();
+        let (first, second) = ([hash(1), hash(2)], [hash(2), hash(3)]);
+        registry.insert(&first);
+        registry.insert(&second);
+        assert_eq!(registry.len(), 3);
+        assert!((1..=3).all(|byte| registry.contains(&hash(byte))));
+
+        // The second set still holds hash 2
+        registry.remove(&first);
+        assert!(!registry.contains(&hash(1)));
+        assert!(registry.contains(&hash(2)) && registry.contains(&hash(3)));
+
+        registry.remove(&second);
+        assert!(registry.is_empty());
+        // Hashes which aren't in the registry are ignored
+        registry.remove(&second);
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn overlapping_mounts() {
+        let (first, second) = (temp_dir("registry-first"), temp_dir("registry-second"));
+        for (dir, seeds) in [(&first, [1, 2]), (&second, [2, 3])] {
+            for seed in seeds {
+                std::fs::write(dir.join(format!("{}.nro", seed)), nro_image(seed)).unwrap();
+            }
+        }
+        let registry = Arc::new(Registry::new());
+        let options = MountOptions::default().load_order(LoadOrder::FileName).registry(registry.clone());
+
+        let first_info = mount_from_directory_with_options(0, &first, |_| true, &options).unwrap();
+        let second_info = mount_from_directory_with_options(0, &second, |_| true, &options).unwrap();
+        // 2.nro is only mounted once
+        assert!(matches!(
+            &second_info.diagnostics[..],
+            [Diagnostic::Skipped { path, reason: SkipReason::AlreadyRegistered }] if *path == second.join("2.nro")
+        ));
+        assert_eq!(registry.len(), 3);
+        assert_eq!(ro::counts(), (2, 3));
+
+        let hashes = first_info.registered_hashes();
+        first_info.unload_all().unwrap();
+        assert!(hashes.iter().all(|hash| !registry.contains(hash)));
+        assert_eq!(registry.len(), 1);
+        for hash in second_info.registered_hashes() {
+            assert!(registry.contains(&hash));
+        }
+
+        second_info.unload_all().unwrap();
+        assert!(registry.is_empty());
+        assert_eq!(ro::counts(), (0, 0));
+        std::fs::remove_dir_all(first).unwrap();
+        std::fs::remove_dir_all(second).unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+//! The `nn::ro`, `nn::crypto` and `nn::settings` calls the loader makes, in one place.
+//!
+//! Unit tests are built against [`mock`] instead, which keeps track of registrations and loaded
+//! modules per thread, so that mounting, reloading and unloading can be exercised off the console.
+
+use std::os::raw::c_char;
+
+use crate::nn;
+use nn::ro::{Module, RegistrationInfo};
+
+#[cfg(test)]
+pub use mock::*;
+#[cfg(not(test))]
+pub use sdk::*;
+
+#[cfg(not(test))]
+mod sdk {
+    use super::*;
+
+    pub unsafe fn load_module(module: *mut Module, image: *const u8, bss: *mut u8, bss_size: usize) -> u32 {
+        nn::ro::LoadModule(module, image as _, bss as _, bss_size as u64, nn::ro::BindFlag_BindFlag_Lazy as i32)
+    }
+
+    pub unsafe fn unload_module(module: *mut Module) -> u32 {
+        nn::ro::UnloadModule(module)
+    }
+
+    pub unsafe fn get_buffer_size(size: &mut usize, image: *const u8) -> u32 {
+        let mut buffer_size = 0;
+        let rc = nn::ro::GetBufferSize(&mut buffer_size, image as _);
+        *size = buffer_size as usize;
+        rc
+    }
+
+    pub unsafe fn register_module_info(info: *mut RegistrationInfo, nrr: *const u8) -> u32 {
+        nn::ro::RegisterModuleInfo(info, nrr as _)
+    }
+
+    pub unsafe fn unregister_module_info(info: *mut RegistrationInfo) -> u32 {
+        nn::ro::UnregisterModuleInfo(info)
+    }
+
+    pub unsafe fn lookup_module_symbol(address: &mut usize, module: *const Module, name: *const c_char) -> u32 {
+        nn::ro::LookupModuleSymbol(address, module, name as _)
+    }
+
+    pub fn sha256(data: &[u8]) -> [u8; 0x20] {
+        let mut hash = [0u8; 0x20];
+        unsafe {
+            nn::crypto::GenerateSha256Hash(hash.as_mut_ptr() as _, 0x20, data.as_ptr() as _, data.len() as u64);
+        }
+        hash
+    }
+
+    /// The running firmware as (major, minor, micro), or `None` if it can't be read.
+    pub fn firmware_version() -> Option<(u8, u8, u8)> {
+        let mut version: nn::settings::system::FirmwareVersion = unsafe { std::mem::zeroed() };
+        if unsafe { nn::settings::system::GetFirmwareVersion(&mut version) } != 0 {
+            return None;
+        }
+        Some((version.major, version.minor, version.micro))
+    }
+}
+
+/// A stand-in for `nn::ro` which checks what the real one does in the ways the loader relies on:
+/// modules have to be authorized by a registered NRR, can't be loaded twice and need a large
+/// enough BSS. Modules are "mapped" where their image is. Hashing isn't SHA-256, just stable and
+/// unlikely to collide.
+#[cfg(test)]
+pub mod mock {
+    use std::cell::RefCell;
+    use std::ffi::CStr;
+
+    use super::*;
+    use crate::{nro, nrr, rc, Sha256Hash, NRR_HASHES_OFFSET};
+
+    #[derive(Default)]
+    pub struct State {
+        /// The registered NRRs, by image address, with their hashes.
+        pub nrrs: Vec<(usize, Vec<Sha256Hash>)>,
+        /// The loaded modules, by the address of their module object, with their hashes.
+        pub modules: Vec<(usize, Sha256Hash)>,
+        /// Symbols every module exports, as offsets from its base.
+        pub symbols: Vec<(String, usize)>,
+        /// Lets this many more loads succeed, then fails the next one with the result code.
+        pub fail_load: Option<(usize, u32)>,
+        pub firmware: Option<(u8, u8, u8)>,
+    }
+
+    thread_local! {
+        static STATE: RefCell<State> = RefCell::new(State::default());
+    }
+
+    pub fn with<R, F: FnOnce(&mut State) -> R>(f: F) -> R {
+        STATE.with(|state| f(&mut state.borrow_mut()))
+    }
+
+    /// How many NRRs and modules are currently registered and loaded.
+    pub fn counts() -> (usize, usize) {
+        with(|state| (state.nrrs.len(), state.modules.len()))
+    }
+
+    unsafe fn image<'a>(image: *const u8) -> &'a [u8] {
+        let header = std::slice::from_raw_parts(image, 0x80);
+        std::slice::from_raw_parts(image, nro::declared_size(header).unwrap())
+    }
+
+    pub unsafe fn load_module(module: *mut Module, image_ptr: *const u8, _bss: *mut u8, bss_size: usize) -> u32 {
+        let image = image(image_ptr);
+        let hash = Sha256Hash::new(image);
+        with(|state| {
+            match &mut state.fail_load {
+                Some((0, rc)) => {
+                    let rc = *rc;
+                    state.fail_load = None;
+                    return rc;
+                },
+                Some((remaining, _)) => *remaining -= 1,
+                None => (),
+            }
+            if !state.nrrs.iter().any(|(_, hashes)| hashes.contains(&hash)) {
+                return rc::NOT_AUTHORIZED;
+            }
+            if state.modules.iter().any(|(_, loaded)| *loaded == hash) {
+                return rc::ALREADY_LOADED;
+            }
+            let Ok(view) = nro::parse(image) else { return rc::INVALID_NRO };
+            if bss_size < view.bss_size {
+                return rc::INVALID_SIZE;
+            }
+
+            let mut object: Box<nnsdk::root::rtld::ModuleObject> = Box::new(std::mem::zeroed());
+            object.module_base = image_ptr as u64;
+            let object = Box::into_raw(object);
+            (*module).ModuleObject = object;
+            state.modules.push((object as usize, hash));
+            0
+        })
+    }
+
+    pub unsafe fn unload_module(module: *mut Module) -> u32 {
+        let object = (*module).ModuleObject;
+        with(|state| {
+            let Some(index) = state.modules.iter().position(|(loaded, _)| *loaded == object as usize) else {
+                return rc::NOT_LOADED;
+            };
+            state.modules.remove(index);
+            drop(Box::from_raw(object));
+            0
+        })
+    }
+
+    pub unsafe fn get_buffer_size(size: &mut usize, image_ptr: *const u8) -> u32 {
+        match nro::parse(image(image_ptr)) {
+            Ok(view) => {
+                *size = view.bss_size;
+                0
+            },
+            Err(_) => rc::INVALID_NRO,
+        }
+    }
+
+    pub unsafe fn register_module_info(info: *mut RegistrationInfo, nrr_ptr: *const u8) -> u32 {
+        let header = std::slice::from_raw_parts(nrr_ptr, NRR_HASHES_OFFSET);
+        let size = u32::from_le_bytes(header[nrr::SIZE_OFFSET..nrr::SIZE_OFFSET + 4].try_into().unwrap());
+        let Ok(hashes) = nrr::read_hashes(std::slice::from_raw_parts(nrr_ptr, size as usize)) else {
+            return rc::INVALID_NRR;
+        };
+        (*info).nrrPtr = nrr_ptr as _;
+        with(|state| state.nrrs.push((nrr_ptr as usize, hashes)));
+        0
+    }
+
+    pub unsafe fn unregister_module_info(info: *mut RegistrationInfo) -> u32 {
+        let nrr = (*info).nrrPtr as usize;
+        with(|state| {
+            let Some(index) = state.nrrs.iter().position(|(registered, _)| *registered == nrr) else {
+                return rc::NOT_REGISTERED;
+            };
+            state.nrrs.remove(index);
+            0
+        })
+    }
+
+    pub unsafe fn lookup_module_symbol(address: &mut usize, module: *const Module, name: *const c_char) -> u32 {
+        let object = (*module).ModuleObject;
+        let name = CStr::from_ptr(name).to_string_lossy();
+        with(|state| {
+            if !state.modules.iter().any(|(loaded, _)| *loaded == object as usize) {
+                return rc::NOT_LOADED;
+            }
+            *address = state.symbols.iter()
+                .find(|(symbol, _)| *symbol == name)
+                .map_or(0, |(_, offset)| (*object).module_base as usize + offset);
+            0
+        })
+    }
+
+    /// Four differently seeded FNV-1a hashes side by side.
+    pub fn sha256(data: &[u8]) -> [u8; 0x20] {
+        let mut hash = [0u8; 0x20];
+        for (seed, chunk) in hash.chunks_exact_mut(8).enumerate() {
+            let mut state = 0xcbf29ce484222325u64 ^ seed as u64;
+            for byte in data {
+                state = (state ^ *byte as u64).wrapping_mul(0x100000001b3);
+            }
+            chunk.copy_from_slice(&state.to_le_bytes());
+        }
+        hash
+    }
+
+    pub fn firmware_version() -> Option<(u8, u8, u8)> {
+        with(|state| state.firmware)
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{mount_nro, register_nrr, LoaderError, MountOptions, Sha256Hash};
+
+const PAGE_SIZE: usize = 0x1000;
+
+const TEXT_OFFSET: usize = 0;
+const RO_OFFSET: usize = TEXT_OFFSET + PAGE_SIZE;
+const DATA_OFFSET: usize = RO_OFFSET + PAGE_SIZE;
+const BSS_OFFSET: usize = DATA_OFFSET + PAGE_SIZE;
+const BSS_SIZE: usize = PAGE_SIZE;
+
+const MOD0_OFFSET: usize = 0x80;
+const CODE_OFFSET: usize = 0x100;
+
+const DYNAMIC_OFFSET: usize = RO_OFFSET;
+const HASH_OFFSET: usize = RO_OFFSET + 0x100;
+const SYMTAB_OFFSET: usize = RO_OFFSET + 0x180;
+const STRTAB_OFFSET: usize = RO_OFFSET + 0x200;
+
+const SYMBOL: &str = "loader_self_test";
+const EXPECTED_RESULT: u32 = 42;
+
+/// Builds a minimal NRO exporting a single function, `loader_self_test`, which returns 42.
+///
+/// The image is laid out as one page each of text, rodata and data, followed by a page of BSS:
+/// - text: the NRO header, the MOD0 header at 0x80 and the function at 0x100
+/// - rodata: `.dynamic`, a SysV hash table, `.dynsym` and `.dynstr`
+/// - data: empty
+/// - BSS: holds the module object written by rtld
+pub(crate) fn build_image() -> Vec<u8> {
+    let mut image = vec![0u8; BSS_OFFSET];
+
+    let mut put = |offset: usize, bytes: &[u8]| image[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+    // NRO header
+    put(0x4, &(MOD0_OFFSET as u32).to_le_bytes());
+    put(0x10, b"NRO0");
+    put(0x18, &(BSS_OFFSET as u32).to_le_bytes());
+    for (index, (offset, size)) in [(TEXT_OFFSET, PAGE_SIZE), (RO_OFFSET, PAGE_SIZE), (DATA_OFFSET, PAGE_SIZE)].into_iter().enumerate() {
+        put(0x20 + index * 8, &(offset as u32).to_le_bytes());
+        put(0x24 + index * 8, &(size as u32).to_le_bytes());
+    }
+    put(0x38, &(BSS_SIZE as u32).to_le_bytes());
+    put(0x40, b"loader-self-test");
+
+    // MOD0, offsets are relative to the header itself
+    let relative = |offset: usize| (offset as i32 - MOD0_OFFSET as i32).to_le_bytes();
+    put(MOD0_OFFSET, b"MOD0");
+    put(MOD0_OFFSET + 0x4, &relative(DYNAMIC_OFFSET));
+    put(MOD0_OFFSET + 0x8, &relative(BSS_OFFSET));
+    put(MOD0_OFFSET + 0xC, &relative(BSS_OFFSET + 0xD0));
+    put(MOD0_OFFSET + 0x10, &relative(MOD0_OFFSET));
+    put(MOD0_OFFSET + 0x14, &relative(MOD0_OFFSET));
+    put(MOD0_OFFSET + 0x18, &relative(BSS_OFFSET));
+
+    // mov w0, #42; ret
+    put(CODE_OFFSET, &0x52800540u32.to_le_bytes());
+    put(CODE_OFFSET + 4, &0xD65F03C0u32.to_le_bytes());
+
+    let strtab = [&[0u8][..], SYMBOL.as_bytes(), &[0u8]].concat();
+
+    // DT_HASH, DT_STRTAB, DT_SYMTAB, DT_STRSZ, DT_SYMENT, DT_NULL
+    let dynamic: [(u64, u64); 6] = [
+        (4, HASH_OFFSET as u64),
+        (5, STRTAB_OFFSET as u64),
+        (6, SYMTAB_OFFSET as u64),
+        (10, strtab.len() as u64),
+        (11, 24),
+        (0, 0),
+    ];
+    for (index, (tag, value)) in dynamic.into_iter().enumerate() {
+        put(DYNAMIC_OFFSET + index * 0x10, &tag.to_le_bytes());
+        put(DYNAMIC_OFFSET + index * 0x10 + 8, &value.to_le_bytes());
+    }
+
+    // nbucket = 1, nchain = 2, bucket[0] = 1, chain = [0, 0]
+    for (index, word) in [1u32, 2, 1, 0, 0].into_iter().enumerate() {
+        put(HASH_OFFSET + index * 4, &word.to_le_bytes());
+    }
+
+    // Symbol 0 is the null symbol, symbol 1 is a global function in the text segment
+    let symbol = SYMTAB_OFFSET + 24;
+    put(symbol, &1u32.to_le_bytes());
+    put(symbol + 4, &[0x12, 0]);
+    put(symbol + 6, &1u16.to_le_bytes());
+    put(symbol + 8, &(CODE_OFFSET as u64).to_le_bytes());
+    put(symbol + 16, &8u64.to_le_bytes());
+
+    put(STRTAB_OFFSET, &strtab);
+
+    image
+}
+
+/// Runs the whole loading pipeline against a tiny built-in NRO: registers an NRR for it, mounts
+/// it, looks up and calls its exported function, then unloads it and unregisters the NRR.
+///
+/// If this passes, registration, relocation, symbol lookup and teardown all work on the running
+/// firmware, which makes it a useful first check when real plugins fail to load.
+pub fn self_test(program_id: u64) -> Result<(), LoaderError> {
+    let image = build_image();
+    let registration = register_nrr(program_id, &[Sha256Hash::new(&image)])?;
+
+    let module = match mount_nro(&image, "loader_self_test.nro", &MountOptions::default()) {
+        Ok(module) => module,
+        Err(e) => {
+            let _ = registration.unregister();
+            return Err(e);
+        }
+    };
+
+    let result = match module.lookup_symbol(SYMBOL) {
+        Err(e) => Err(LoaderError::SelfTestFailed(e.to_string())),
+        Ok(address) => {
+            let function: extern "C" fn() -> u32 = unsafe { std::mem::transmute(address) };
+            match function() {
+                EXPECTED_RESULT => Ok(()),
+                value => Err(LoaderError::SelfTestFailed(format!("{} returned {}, expected {}", SYMBOL, value, EXPECTED_RESULT)))
+            }
+        }
+    };
+
+    let unloaded = module.unload();
+    let unregistered = registration.unregister();
+    result.and(unloaded).and(unregistered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nro::{self, Segment};
+
+    const DT_HASH: u64 = 4;
+    const DT_STRTAB: u64 = 5;
+    const DT_SYMTAB: u64 = 6;
+
+    fn elf_hash(name: &str) -> u32 {
+        name.bytes().fold(0u32, |hash, byte| {
+            let hash = (hash << 4) + byte as u32;
+            let high = hash & 0xF000_0000;
+            (hash ^ (high >> 24)) & !high
+        })
+    }
+
+    /// Looks `name` up through the image's SysV hash table, like rtld does, returning its value.
+    fn find_symbol(image: &[u8], dynamic: &[(u64, u64)], name: &str) -> Option<u64> {
+        let entry = |tag| dynamic.iter().find(|(entry, _)| *entry == tag).map(|(_, value)| *value as usize);
+        let (hash, strtab, symtab) = (entry(DT_HASH)?, entry(DT_STRTAB)?, entry(DT_SYMTAB)?);
+        let word = |offset: usize| u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap()) as usize;
+
+        let buckets = word(hash);
+        let chain = hash + 8 + buckets * 4;
+        let mut index = word(hash + 8 + elf_hash(name) as usize % buckets * 4);
+        while index != 0 {
+            let symbol = symtab + index * 24;
+            let start = strtab + word(symbol);
+            let end = start + image[start..].iter().position(|byte| *byte == 0)?;
+            if &image[start..end] == name.as_bytes() {
+                return Some(u64::from_le_bytes(image[symbol + 8..symbol + 16].try_into().unwrap()));
+            }
+            index = word(chain + index * 4);
+        }
+        None
+    }
+
+    #[test]
+    fn image_is_valid() {
+        let image = build_image();
+        nro::validate(&image).unwrap();
+
+        let view = nro::parse(&image).unwrap();
+        assert_eq!(view.size, image.len());
+        assert_eq!(
+            [view.text, view.ro, view.data_segment],
+            [TEXT_OFFSET, RO_OFFSET, DATA_OFFSET].map(|offset| Segment { offset, size: PAGE_SIZE })
+        );
+        assert_eq!(view.bss_size, BSS_SIZE);
+
+        let mod0 = view.mod0.unwrap();
+        assert_eq!(mod0.offset, MOD0_OFFSET);
+        assert_eq!(mod0.dynamic, Some(DYNAMIC_OFFSET));
+        assert_eq!((mod0.bss_start, mod0.bss_end), (BSS_OFFSET, BSS_OFFSET + 0xD0));
+        assert_eq!(mod0.module_object, BSS_OFFSET);
+        // Still valid once it's been fixed up for mounting
+        let mut fixed = image.clone();
+        nro::fix_bss_size(&mut fixed).unwrap();
+        nro::validate(&fixed).unwrap();
+    }
+
+    #[test]
+    fn symbol_resolves() {
+        let image = build_image();
+        let dynamic = nro::parse(&image).unwrap().dynamic_entries();
+        assert_eq!(find_symbol(&image, &dynamic, SYMBOL), Some(CODE_OFFSET as u64));
+        assert_eq!(find_symbol(&image, &dynamic, "loader_self_tes"), None);
+
+        // mov w0, #imm16 has the immediate in bits 5 to 20
+        let instruction = u32::from_le_bytes(image[CODE_OFFSET..CODE_OFFSET + 4].try_into().unwrap());
+        assert_eq!((instruction >> 5) & 0xFFFF, EXPECTED_RESULT);
+    }
+}
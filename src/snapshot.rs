@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+
+use crate::{
+    mount_plugins, Diagnostic, LoaderError, MountInfo, MountOptions, NroFile, PreparedPlugin, Priority, Sha256Hash,
+    SkipReason,
+};
+
+/// A mounted module as recorded by [`MountInfo::snapshot`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub hash: Sha256Hash,
+}
+
+/// The set of plugins which was mounted at some point, in load order, for going back to it later
+/// with [`MountInfo::restore`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PluginSnapshot {
+    entries: Vec<SnapshotEntry>,
+}
+
+impl PluginSnapshot {
+    pub fn entries(&self) -> &[SnapshotEntry] {
+        &self.entries
+    }
+}
+
+impl MountInfo {
+    /// Records the name, path and hash of every mounted module, in load order. Modules which
+    /// weren't mounted from a file (e.g. with [`crate::mount_nro`]) can't be restored, so they're
+    /// left out.
+    pub fn snapshot(&self) -> PluginSnapshot {
+        let entries = self.iter_load_order()
+            .filter_map(|module| {
+                let path = module.path()?.to_path_buf();
+                Some(SnapshotEntry { name: module.name().to_owned(), path, hash: module.hash() })
+            })
+            .collect();
+        PluginSnapshot { entries }
+    }
+
+    /// Unloads everything, see [`MountInfo::unload_all`], and mounts the files in `snapshot`
+    /// again under a new NRR, in the snapshot's order unless `options` set a [`crate::LoadOrder`].
+    ///
+    /// Every file is read before anything is unloaded. Files which no longer exist are reported as
+    /// [`SkipReason::Missing`], and files whose contents changed since the snapshot fail with
+    /// [`LoaderError::HashMismatch`] rather than mounting something else.
+    ///
+    /// Pinned modules (see [`MountInfo::pin`]) aren't unloaded, so they aren't mounted again
+    /// either. They're carried over as they are, still pinned and ahead of the restored modules,
+    /// along with the NRRs holding their hashes.
+    pub fn restore(self, snapshot: &PluginSnapshot, options: &MountOptions) -> Result<MountInfo, LoaderError> {
+        let reader = options.reader();
+        let mut missing = Vec::new();
+        let mut plugins = Vec::with_capacity(snapshot.entries.len());
+        for entry in snapshot.entries.iter().filter(|entry| !self.is_pinned(&entry.name)) {
+            let nro = match NroFile::prepare(&reader, &entry.path) {
+                Err(LoaderError::IO(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                    missing.push(entry.path.clone());
+                    continue;
+                },
+                Ok((_, hash)) if hash != entry.hash => Err(LoaderError::HashMismatch { expected: entry.hash, actual: hash }),
+                nro => nro,
+            };
+            let plugin = PreparedPlugin { path: entry.path.clone(), priority: Priority::default(), nro };
+            options.hooks.fire_hashed(&plugin);
+            plugins.push(plugin);
+        }
+        if options.strict {
+            if let Some(path) = missing.first() {
+                let source = Box::new(LoaderError::Skipped(SkipReason::Missing));
+                return Err(LoaderError::StrictModeViolation { path: path.clone(), source });
+            }
+        }
+
+        let program_id = self.program_id;
+        let (pinned, registrations) = self.unload_unpinned()?;
+        let mut info = mount_plugins(program_id, plugins, Vec::new(), Vec::new(), options)?;
+        info.diagnostics.extend(missing.into_iter().map(|path| Diagnostic::Skipped { path, reason: SkipReason::Missing }));
+
+        info.pinned = pinned.iter().map(|module| module.name().to_owned()).collect();
+        info.names.splice(..0, pinned.iter().map(|module| module.name().to_owned()));
+        info.modules.splice(..0, pinned.into_iter().map(Ok));
+        info.registrations.splice(..0, registrations);
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{nro_image, temp_dir};
+    use crate::{mount_from_directory_with_options, ro, LoadOrder, MountOptions};
+
+    #[test]
+    fn pinned_modules_are_carried_over() {
+        let dir = temp_dir("restore-pinned");
+        for (seed, name) in [(1, "a.nro"), (2, "b.nro"), (3, "c.nro")] {
+            std::fs::write(dir.join(name), nro_image(seed)).unwrap();
+        }
+        let options = MountOptions::default().load_order(LoadOrder::FileName);
+        let mut info = mount_from_directory_with_options(0, &dir, |_| true, &options).unwrap();
+        info.pin("b.nro").unwrap();
+        let b = info.iter_load_order().find(|module| module.name() == "b.nro").unwrap().base();
+
+        let snapshot = info.snapshot();
+        let mut info = info.restore(&snapshot, &options).unwrap();
+        // b.nro is still loaded from before, under its old NRR, and the others got a new one
+        let names: Vec<_> = info.iter_load_order().map(|module| module.name()).collect();
+        assert_eq!(names, ["b.nro", "a.nro", "c.nro"]);
+        assert_eq!(info.names, ["b.nro", "a.nro", "c.nro"]);
+        assert_eq!(info.iter_load_order().next().unwrap().base(), b);
+        assert!(info.is_pinned("b.nro"));
+        assert_eq!(ro::counts(), (2, 3));
+
+        assert!(info.unpin("b.nro"));
+        info.unload_all().unwrap();
+        assert_eq!(ro::counts(), (0, 0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
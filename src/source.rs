@@ -0,0 +1,139 @@
+use std::ffi::{CStr, CString};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::{nn, LoaderError};
+
+/// Somewhere plugins can be listed and read from. [`StdFs`] is used unless another source is set
+/// with [`crate::MountOptions::source`].
+pub trait PluginSource {
+    /// Lists the paths of every regular file directly inside `path`. Directories and special files
+    /// such as FIFOs or devices must be left out, as reading them either fails or blocks.
+    ///
+    /// A directory which doesn't exist should be reported as [`std::io::ErrorKind::NotFound`], which
+    /// is turned into [`LoaderError::PluginDirMissing`].
+    fn list(&self, path: &Path) -> std::io::Result<Vec<PathBuf>>;
+
+    /// Reads the whole file at `path`.
+    ///
+    /// Sources which are able to cancel a read should abandon it once `timeout` has passed and
+    /// return [`LoaderError::ReadTimeout`], so that a single stalled file doesn't hold up the
+    /// rest of the plugins.
+    fn read(&self, path: &Path, timeout: Option<Duration>) -> Result<Vec<u8>, LoaderError>;
+}
+
+/// Reads plugins through `std::fs`.
+///
+/// `std::fs` reads block and can't be cancelled, so the read timeout is ignored by this source.
+pub struct StdFs;
+
+impl PluginSource for StdFs {
+    fn list(&self, path: &Path) -> std::io::Result<Vec<PathBuf>> {
+        // `ReadDir` doesn't report a useful size hint, so collect the entries up front in order to
+        // reserve for them before filtering.
+        let entries: Vec<_> = std::fs::read_dir(path)?.collect();
+
+        // `metadata` follows symlinks, so a link to a plugin is still picked up
+        let mut paths = Vec::with_capacity(entries.len());
+        paths.extend(
+            entries.into_iter()
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| std::fs::metadata(path).map(|metadata| metadata.is_file()).unwrap_or(false))
+        );
+        Ok(paths)
+    }
+
+    fn read(&self, path: &Path, _timeout: Option<Duration>) -> Result<Vec<u8>, LoaderError> {
+        std::fs::read(path).map_err(Into::into)
+    }
+}
+
+/// Reads plugins through the SDK's `nn::fs` APIs, for content `std::fs` can't see, such as the
+/// title's own romfs.
+///
+/// Paths are `nn::fs` paths, made of a mount name and a path inside it, e.g. `rom:/plugins`. The
+/// mount has to exist already: the game mounts its romfs as `rom` itself, usually early in boot,
+/// while anything else has to be mounted with the matching `nn::fs::Mount*` call first. Entries
+/// are joined onto the directory path, so pass the directory without a trailing `/`.
+///
+/// Like [`StdFs`], reads block and the read timeout is ignored.
+pub struct NnFs;
+
+/// `nn::fs` reports a missing path with this result code (module 2, description 1).
+const PATH_NOT_FOUND: u32 = 2 | (1 << 9);
+
+fn nn_path(path: &Path) -> std::io::Result<CString> {
+    path.to_str()
+        .and_then(|path| CString::new(path).ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "path isn't valid for nn::fs"))
+}
+
+fn nn_error(operation: &str, rc: u32) -> Error {
+    let kind = if rc == PATH_NOT_FOUND { ErrorKind::NotFound } else { ErrorKind::Other };
+    Error::new(kind, format!("nn::fs::{} failed: {:#x}", operation, rc))
+}
+
+impl PluginSource for NnFs {
+    fn list(&self, path: &Path) -> std::io::Result<Vec<PathBuf>> {
+        let nn_path = nn_path(path)?;
+        unsafe {
+            let mut handle: nn::fs::DirectoryHandle = std::mem::zeroed();
+            let mode = nn::fs::OpenDirectoryMode_OpenDirectoryMode_File as i32;
+            let rc = nn::fs::OpenDirectory(&mut handle, nn_path.as_ptr() as _, mode);
+            if rc != 0 {
+                return Err(nn_error("OpenDirectory", rc));
+            }
+
+            let mut count = 0;
+            let rc = nn::fs::GetDirectoryEntryCount(&mut count, handle);
+            if rc != 0 {
+                nn::fs::CloseDirectory(handle);
+                return Err(nn_error("GetDirectoryEntryCount", rc));
+            }
+
+            let mut entries: Vec<nn::fs::DirectoryEntry> = (0..count).map(|_| std::mem::zeroed()).collect();
+            let mut read = 0;
+            let rc = nn::fs::ReadDirectory(&mut read, entries.as_mut_ptr(), handle, count);
+            nn::fs::CloseDirectory(handle);
+            if rc != 0 {
+                return Err(nn_error("ReadDirectory", rc));
+            }
+
+            // The directory was opened for files only, so there's nothing else to filter out
+            entries.truncate(read.max(0) as usize);
+            Ok(entries.iter()
+                // Names are at most 768 bytes, so the last byte is always a terminator
+                .map(|entry| path.join(CStr::from_ptr(entry.name.as_ptr() as _).to_string_lossy().as_ref()))
+                .collect())
+        }
+    }
+
+    fn read(&self, path: &Path, _timeout: Option<Duration>) -> Result<Vec<u8>, LoaderError> {
+        let nn_path = nn_path(path)?;
+        unsafe {
+            let mut handle: nn::fs::FileHandle = std::mem::zeroed();
+            let rc = nn::fs::OpenFile(&mut handle, nn_path.as_ptr() as _, nn::fs::OpenMode_OpenMode_Read as i32);
+            if rc != 0 {
+                return Err(nn_error("OpenFile", rc).into());
+            }
+
+            let mut size = 0;
+            let rc = nn::fs::GetFileSize(&mut size, handle);
+            if rc != 0 {
+                nn::fs::CloseFile(handle);
+                return Err(nn_error("GetFileSize", rc).into());
+            }
+
+            let mut data = vec![0u8; size.max(0) as usize];
+            let mut read = 0;
+            let rc = nn::fs::ReadFile1(&mut read, handle, 0, data.as_mut_ptr() as _, data.len() as u64);
+            nn::fs::CloseFile(handle);
+            if rc != 0 {
+                return Err(nn_error("ReadFile", rc).into());
+            }
+            data.truncate(read as usize);
+            Ok(data)
+        }
+    }
+}
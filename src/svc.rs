@@ -0,0 +1,189 @@
+//! Supervisor calls which the SDK bindings don't cover, and the cache maintenance which goes with
+//! them.
+
+use std::ops::Range;
+
+/// The layout `svcQueryMemory` writes.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MemoryInfo {
+    pub address: u64,
+    pub size: u64,
+    pub memory_type: u32,
+    pub attribute: u32,
+    pub permission: u32,
+    pub ipc_ref_count: u32,
+    pub device_ref_count: u32,
+    _padding: u32,
+}
+
+/// Queries the memory region containing `address`. Returns `None` if the query fails, or on
+/// targets other than the Switch.
+#[cfg(target_arch = "aarch64")]
+pub fn query_memory(address: usize) -> Option<MemoryInfo> {
+    let mut info = MemoryInfo::default();
+    let rc: usize;
+    unsafe {
+        // x0 holds the output pointer going in and the result coming out, x1 gets the page info
+        std::arch::asm!(
+            "svc 0x6",
+            inlateout("x0") &mut info as *mut MemoryInfo as usize => rc,
+            lateout("x1") _,
+            in("x2") address,
+            options(nostack)
+        );
+    }
+    (rc as u32 == 0).then_some(info)
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+pub fn query_memory(_address: usize) -> Option<MemoryInfo> {
+    None
+}
+
+/// The pseudo-handle which refers to the current process.
+#[cfg(target_arch = "aarch64")]
+const CURRENT_PROCESS: usize = 0xFFFF8001;
+
+/// `svcGetInfo` info types.
+const INFO_ALIAS_REGION_ADDRESS: u32 = 2;
+const INFO_ALIAS_REGION_SIZE: u32 = 3;
+const INFO_HEAP_REGION_ADDRESS: u32 = 4;
+const INFO_HEAP_REGION_SIZE: u32 = 5;
+const INFO_ASLR_REGION_ADDRESS: u32 = 12;
+const INFO_ASLR_REGION_SIZE: u32 = 13;
+const INFO_STACK_REGION_ADDRESS: u32 = 14;
+const INFO_STACK_REGION_SIZE: u32 = 15;
+
+/// The `svcQueryMemory` memory type of unmapped memory.
+const MEMORY_TYPE_FREE: u32 = 0;
+
+/// Reads one of the current process' `svcGetInfo` values. Returns `None` if the call fails, or on
+/// targets other than the Switch.
+#[cfg(target_arch = "aarch64")]
+fn get_info(info_type: u32) -> Option<u64> {
+    let rc: usize;
+    let value: u64;
+    unsafe {
+        std::arch::asm!(
+            "svc 0x29",
+            lateout("x0") rc,
+            inlateout("x1") info_type as usize => value,
+            in("x2") CURRENT_PROCESS,
+            in("x3") 0usize,
+            options(nostack)
+        );
+    }
+    (rc as u32 == 0).then_some(value)
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn get_info(_info_type: u32) -> Option<u64> {
+    None
+}
+
+fn get_region(address_type: u32, size_type: u32) -> Option<Range<u64>> {
+    let start = get_info(address_type)?;
+    Some(start..start.checked_add(get_info(size_type)?)?)
+}
+
+/// How much of `range` lies outside every range in `reserved`, which must not overlap each other.
+fn outside(range: Range<u64>, reserved: &[Range<u64>]) -> u64 {
+    let overlap = |other: &Range<u64>| other.end.min(range.end).saturating_sub(other.start.max(range.start));
+    (range.end - range.start) - reserved.iter().map(overlap).sum::<u64>()
+}
+
+/// The total size of the unmapped memory `nn::ro` could map modules into: the free memory in the
+/// process' ASLR region, leaving out the heap, alias and stack regions inside it, which modules are
+/// never mapped into. Returns `None` if it can't be queried, which includes every target other than
+/// the Switch.
+///
+/// This is the sum of every free region, and a module needs a single region large enough for its
+/// image and BSS, so a fragmented address space can still fail to fit a module smaller than this.
+pub fn available_code_space() -> Option<usize> {
+    let Range { start, end } = get_region(INFO_ASLR_REGION_ADDRESS, INFO_ASLR_REGION_SIZE)?;
+    let reserved = [
+        get_region(INFO_HEAP_REGION_ADDRESS, INFO_HEAP_REGION_SIZE)?,
+        get_region(INFO_ALIAS_REGION_ADDRESS, INFO_ALIAS_REGION_SIZE)?,
+        get_region(INFO_STACK_REGION_ADDRESS, INFO_STACK_REGION_SIZE)?,
+    ];
+
+    let mut free = 0;
+    let mut address = start;
+    while address < end {
+        let info = query_memory(address as usize)?;
+        let region_end = info.address.saturating_add(info.size).min(end);
+        if region_end <= address {
+            break;
+        }
+        if info.memory_type == MEMORY_TYPE_FREE {
+            free += outside(address..region_end, &reserved);
+        }
+        address = region_end;
+    }
+    Some(free as usize)
+}
+
+/// Cleans the data cache and invalidates the instruction cache over `start..start + len`, so the
+/// CPU doesn't run stale instructions from it. Does nothing on targets other than the Switch.
+///
+/// # Safety
+/// The whole range has to be mapped and readable.
+#[cfg(target_arch = "aarch64")]
+pub unsafe fn flush_icache(start: usize, len: usize) {
+    let ctr: usize;
+    std::arch::asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack));
+    // CTR_EL0 holds log2 of the smallest line sizes in words
+    let dcache_line = 4 << ((ctr >> 16) & 0xF);
+    let icache_line = 4 << (ctr & 0xF);
+    let end = start + len;
+
+    let mut address = start & !(dcache_line - 1);
+    while address < end {
+        std::arch::asm!("dc cvau, {}", in(reg) address, options(nostack));
+        address += dcache_line;
+    }
+    std::arch::asm!("dsb ish", options(nostack));
+
+    let mut address = start & !(icache_line - 1);
+    while address < end {
+        std::arch::asm!("ic ivau, {}", in(reg) address, options(nostack));
+        address += icache_line;
+    }
+    std::arch::asm!("dsb ish", "isb", options(nostack));
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+pub unsafe fn flush_icache(_start: usize, _len: usize) {}
+
+/// A region's permission bits, as reported by `svcQueryMemory`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryPermission(pub u32);
+
+impl MemoryPermission {
+    pub const READ: Self = Self(1);
+    pub const READ_WRITE: Self = Self(3);
+    pub const READ_EXECUTE: Self = Self(5);
+}
+
+impl std::fmt::Display for MemoryPermission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bit = |mask, c| if self.0 & mask != 0 { c } else { '-' };
+        write!(f, "{}{}{}", bit(1, 'r'), bit(2, 'w'), bit(4, 'x'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_regions_are_left_out() {
+        assert_eq!(outside(0x1000..0x5000, &[]), 0x4000);
+        assert_eq!(outside(0x1000..0x5000, std::slice::from_ref(&(0x2000..0x3000))), 0x3000);
+        // Only the part which overlaps counts
+        assert_eq!(outside(0x1000..0x5000, &[0..0x2000, 0x4800..0x9000]), 0x2800);
+        assert_eq!(outside(0x1000..0x5000, &[0x5000..0x6000, 0..0x1000]), 0x4000);
+        assert_eq!(outside(0x1000..0x5000, std::slice::from_ref(&(0..0x10000))), 0);
+    }
+}
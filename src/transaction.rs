@@ -0,0 +1,138 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    discover, mount_plugins, prepare_all, Discovered, LoaderError, MountInfo, MountOptions, NroFile,
+    PreparedPlugin, Priority,
+};
+
+/// Collects plugins from several places and mounts them all at once, under a single NRR.
+///
+/// Nothing touches `nn::ro` until [`MountTransaction::commit`], which registers every collected
+/// hash together and then mounts the plugins in the order they were added. Combined with
+/// [`MountOptions::strict`], either every plugin is mounted or none are.
+#[derive(Default)]
+pub struct MountTransaction {
+    plugins: Vec<PreparedPlugin>,
+    hidden: Vec<PathBuf>,
+    duplicates: Vec<PathBuf>,
+}
+
+impl MountTransaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the plugins in `path` which pass `validator`, read with the source and hooks from
+    /// `options`. Plugins which can't be read or parsed are kept and end up as failed modules in
+    /// the [`MountInfo`], like they would with [`crate::mount_from_directory_with_options`]. Fails
+    /// only if the directory can't be listed.
+    pub fn add_directory<P: AsRef<Path>, F: Fn(&Path) -> bool>(
+        &mut self,
+        path: P,
+        validator: F,
+        options: &MountOptions
+    ) -> Result<&mut Self, LoaderError> {
+        let Discovered { plugins, hidden, duplicates } = discover(options, path.as_ref(), validator)?;
+        self.plugins.extend(prepare_all(plugins, options));
+        self.hidden.extend(hidden);
+        self.duplicates.extend(duplicates);
+        Ok(self)
+    }
+
+    /// Adds an NRO which is already in memory, called `name`. The image is validated right away,
+    /// so a malformed one fails here rather than at commit. The name is only checked against
+    /// [`MountOptions::name_policy`] when it's mounted.
+    pub fn add_bytes(&mut self, bytes: &[u8], name: &str) -> Result<&mut Self, LoaderError> {
+        let mut nro = NroFile { data: bytes.to_vec(), name: name.to_string(), path: None };
+        crate::nro::parse(&nro.data)?;
+        nro.fix_bss_size()?;
+        let hash = nro.hash();
+        let path = PathBuf::from(name);
+        self.plugins.push(PreparedPlugin { path, priority: Priority::default(), nro: Ok((nro, hash)) });
+        Ok(self)
+    }
+
+    /// How many plugins have been added so far, including ones which failed to read.
+    pub fn len(&self) -> usize {
+        self.plugins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    /// Registers every collected hash in one NRR (or several, with
+    /// [`MountOptions::max_hashes_per_nrr`]) and mounts the plugins, exactly like a directory mount
+    /// with `options`, which decides e.g. overrides, load order and strict mode.
+    pub fn commit(self, program_id: u64, options: &MountOptions) -> Result<MountInfo, LoaderError> {
+        self.commit_with(|plugins, hidden, duplicates| mount_plugins(program_id, plugins, hidden, duplicates, options))
+    }
+
+    /// Hands everything collected to `mount` at once, which is where `nn::ro` first gets involved.
+    fn commit_with<M>(self, mount: M) -> Result<MountInfo, LoaderError>
+    where
+        M: FnOnce(Vec<PreparedPlugin>, Vec<PathBuf>, Vec<PathBuf>) -> Result<MountInfo, LoaderError>
+    {
+        mount(self.plugins, self.hidden, self.duplicates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ro, LoadedModule};
+    use crate::tests::{nro_image, temp_dir};
+
+    fn staged(dir: &Path) -> MountTransaction {
+        std::fs::write(dir.join("a.nro"), nro_image(1)).unwrap();
+        std::fs::write(dir.join(".hidden.nro"), nro_image(2)).unwrap();
+
+        let mut transaction = MountTransaction::new();
+        transaction.add_directory(dir, |_| true, &MountOptions::default()).unwrap();
+        transaction.add_bytes(&nro_image(3), "b.nro").unwrap();
+        // The same image as a.nro under another name
+        transaction.add_bytes(&nro_image(1), "copy.nro").unwrap();
+        transaction
+    }
+
+    #[test]
+    fn staging_waits_for_commit() {
+        let dir = temp_dir("transaction-staging");
+        let transaction = staged(&dir);
+        assert_eq!(transaction.len(), 3);
+        assert_eq!(ro::counts(), (0, 0));
+
+        let error = transaction.commit_with(|plugins, hidden, duplicates| {
+            let paths: Vec<_> = plugins.iter().map(|plugin| plugin.path.clone()).collect();
+            assert_eq!(paths, [dir.join("a.nro"), PathBuf::from("b.nro"), PathBuf::from("copy.nro")]);
+            assert_eq!(hidden, [dir.join(".hidden.nro")]);
+            assert!(duplicates.is_empty());
+            Err(LoaderError::NotReloadable)
+        });
+        assert!(matches!(error, Err(LoaderError::NotReloadable)));
+        assert_eq!(ro::counts(), (0, 0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn malformed_bytes_fail_right_away() {
+        let mut transaction = MountTransaction::new();
+        assert!(transaction.add_bytes(&[0; 0x10], "broken.nro").is_err());
+        assert!(transaction.is_empty());
+    }
+
+    #[test]
+    fn commit_registers_once() {
+        let dir = temp_dir("transaction-commit");
+        let info = staged(&dir).commit(0x0100_0000_0000_1234, &MountOptions::default()).unwrap();
+
+        let names: Vec<_> = info.iter_load_order().map(LoadedModule::name).collect();
+        assert_eq!(names, ["a.nro", "b.nro"]);
+        assert_eq!(info.deduped, [(PathBuf::from("copy.nro"), dir.join("a.nro"))]);
+        assert_eq!(ro::counts(), (1, 2));
+
+        info.unload_all().unwrap();
+        assert_eq!(ro::counts(), (0, 0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}